@@ -6,411 +6,3881 @@ use anyhow::Result;
 use regex::Regex;
 use rmcp::{ServiceExt, transport::stdio};
 use rmcp::{
-    handler::server::{router::tool::ToolRouter, tool::Parameters},
-    model::{CallToolResult, Content, ErrorData, ServerInfo, ServerCapabilities, Implementation, ProtocolVersion},
-    ServerHandler,
+    handler::server::{router::tool::ToolRouter, tool::{Parameters, ToolCallContext}},
+    model::{CallToolRequestParam, CallToolResult, Content, ErrorData, ListToolsResult, PaginatedRequestParam, ServerInfo, ServerCapabilities, Implementation, ProtocolVersion},
+    service::RequestContext,
+    RoleServer, ServerHandler,
 };
-use rmcp_macros::{tool, tool_router, tool_handler};
+use rmcp_macros::{tool, tool_router};
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite, Row, Column, ValueRef};
-use std::sync::Arc;
+use sqlx::{sqlite::{SqlitePoolOptions, SqliteRow}, Pool, Sqlite, Row, Column, TypeInfo, ValueRef};
+use lru::LruCache;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use std::future::Future;
 use base64::engine::general_purpose::STANDARD as B64;
 use base64::Engine;
+use futures::TryStreamExt;
 
 #[derive(Clone)]
 struct AppState {
     pool: Pool<Sqlite>,
     ident_re: Regex,
+    // None means every table is allowed. Both lists are lowercased when loaded
+    allowed_tables: Option<HashSet<String>>,
+    denied_tables: HashSet<String>,
+    // Results of write calls keyed by "<tool>:<idempotency_key>", replayed on retries
+    request_cache: Arc<Mutex<LruCache<String, (Instant, CallToolResult)>>>,
+    // Global cap on rows returned by list-returning tools (MAX_RESULT_ROWS)
+    max_result_rows: usize,
+    // Per-tool overrides of max_result_rows from config.toml [tool_limits]
+    tool_limits: HashMap<String, usize>,
+    // table -> columns whose values are returned as "***" (MASKED_COLUMNS, a JSON object)
+    masked_columns: HashMap<String, Vec<String>>,
+    // Default for SelectInput.stable (WARP_SQLITE_STABLE_ORDER)
+    stable_order: bool,
+    // Most bind parameters in one statement (WARP_SQLITE_MAX_PARAMS); IN lists are chunked to stay under it
+    max_params: usize,
+    // Whether the linked SQLite has jsonb() (3.45+), probed at startup
+    jsonb_supported: bool,
+    // Results called with compress are gzipped once their JSON exceeds this many bytes
+    // (WARP_SQLITE_COMPRESS_THRESHOLD, or COMPRESS_THRESHOLD_BYTES)
+    compress_threshold: usize,
+    // Connections currently running a select, for sqlite_cancel; most recent last
+    active_queries: Arc<Mutex<Vec<ActiveQuery>>>,
+    // Per-tool call counters since startup, keyed by tool name
+    tool_stats: Arc<Mutex<HashMap<String, ToolStats>>>,
+    // SQLITE_BUSY/SQLITE_LOCKED failures since startup, keyed by the call's table argument (else the tool name)
+    busy_stats: Arc<Mutex<HashMap<String, BusyStats>>>,
+    // config.toml [rate_limits]; tools without an entry are unlimited
+    rate_limits: HashMap<String, RateLimitConfig>,
+    rate_buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
+    // Bounds tool calls in flight (MAX_CONCURRENT_QUERIES); waits longer than semaphore_timeout fail as busy
+    query_permits: Arc<tokio::sync::Semaphore>,
+    semaphore_timeout: Duration,
+    // READ_ONLY: WRITE_TOOLS are refused in call_tool
+    read_only: bool,
+    // Set on SIGTERM/SIGINT; new tool calls are refused while in-flight ones drain
+    shutting_down: Arc<AtomicBool>,
+    in_flight: Arc<AtomicUsize>,
 }
 
+struct ActiveQuery { id: String, seq: u64, handle: usize }
+
+// Rows from run_select plus the envelope metadata every select-style tool returns
+struct SelectedRows { rows: Vec<serde_json::Map<String, Value>>, truncated: bool, limit_applied: Option<i64> }
+impl SelectedRows {
+    fn into_json(self) -> Value {
+        serde_json::json!({ "row_count": self.rows.len(), "truncated": self.truncated, "limit_applied": self.limit_applied, "rows": self.rows })
+    }
+}
+
+// Counts a tool call as in flight until it returns or its future is dropped
+struct InFlightGuard<'a>(&'a AtomicUsize);
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) { self.0.fetch_sub(1, Ordering::SeqCst); }
+}
+
+// Unregisters an in-flight query when the select finishes or its future is dropped, before the connection goes back to the pool
+struct ActiveQueryGuard { queries: Arc<Mutex<Vec<ActiveQuery>>>, seq: u64 }
+impl Drop for ActiveQueryGuard {
+    fn drop(&mut self) { self.queries.lock().unwrap().retain(|q| q.seq != self.seq); }
+}
+
+#[derive(Default, Clone, Copy)]
+struct ToolStats { call_count: u64, total_us: u64, error_count: u64 }
+
+#[derive(Default, Clone, Copy)]
+struct BusyStats { busy_count: u64, last_busy_ms: u64 }
+
+#[derive(Deserialize, Clone, Copy)]
+struct RateLimitConfig { rps: f64, burst: usize }
+
+struct TokenBucket { tokens: f64, refilled_at: Instant }
+impl TokenBucket {
+    // Takes a token, or returns how long until one is available
+    fn take(&mut self, limit: RateLimitConfig) -> std::result::Result<(), Duration> {
+        let now = Instant::now();
+        let burst = limit.burst.max(1) as f64;
+        self.tokens = (self.tokens + now.duration_since(self.refilled_at).as_secs_f64() * limit.rps).min(burst);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 { self.tokens -= 1.0; return Ok(()); }
+        if limit.rps <= 0.0 { return Err(Duration::MAX); }
+        Err(Duration::try_from_secs_f64((1.0 - self.tokens) / limit.rps).unwrap_or(Duration::MAX))
+    }
+}
+
+const DEFAULT_MAX_RESULT_ROWS: usize = 10_000;
+const MAX_CONNECTIONS: u32 = 5;
+const DEFAULT_SEMAPHORE_TIMEOUT_MS: u64 = 5_000;
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+// Tools that change the database (or, for export, write it elsewhere); refused while READ_ONLY is set
+const WRITE_TOOLS: &[&str] = &[
+    "sqlite_insert", "sqlite_upsert_or_fail", "sqlite_insert_default_row", "sqlite_update", "sqlite_compare_and_set",
+    "sqlite_delete", "sqlite_truncate", "sqlite_increment", "sqlite_insert_from_select", "sqlite_copy_rows",
+    "sqlite_merge_tables", "sqlite_swap_tables", "sqlite_delete_batch", "sqlite_update_where_in",
+    "sqlite_rebuild_fts", "sqlite_reindex", "sqlite_history_table", "sqlite_create_table", "sqlite_drop_table",
+    "sqlite_export_encrypted",
+    "sqlite_set_user_version", "sqlite_set_application_id",
+    "mcp_register_server", "mcp_unregister_server", "mcp_set_env",
+    "notebook_create", "notebook_append", "notebook_delete", "notebook_bulk_delete", "notebook_merge",
+    "notebook_tag_add", "notebook_tag_remove",
+];
+// Don't touch the database, and sqlite_cancel must get through while selects hold every permit
+const UNLIMITED_TOOLS: &[&str] = &["sqlite_cancel", "sqlite_query_stats", "sqlite_lock_stats"];
+// SQLite's default SQLITE_MAX_VARIABLE_NUMBER; default for WARP_SQLITE_MAX_PARAMS
+const DEFAULT_MAX_PARAMS: usize = 999;
+// mcp_get_env hides values of keys containing these when mcp_environment_variables.environment_variables is masked
+const SENSITIVE_ENV_KEY_PARTS: &[&str] = &["TOKEN", "SECRET", "KEY", "PASSWORD", "PASSWD", "AUTH", "CREDENTIAL"];
+// Optional per-notebook history (notebook_id column); notebook_bulk_delete cascades into it when present
+const NOTEBOOK_VERSIONS_TABLE: &str = "notebook_versions";
+// Keys accepted under [tool_limits].<tool>; both mean the same row cap
+const TOOL_LIMIT_KEYS: &[&str] = &["max_rows", "max_items"];
+const DEFAULT_COMPRESS_THRESHOLD: usize = 64 * 1024;
+// Rows per page for sqlite_select with chunked
+const DEFAULT_CHUNK_ROWS: usize = 1000;
+// Marks a result whose JSON was gzipped and base64-encoded into data
+const COMPRESSED_ENCODING: &str = "gzip+base64";
+
+impl AppState {
+    // Row cap for a tool: its [tool_limits] entry, else the tool's own default bounded by the global cap
+    fn row_limit(&self, tool: &str, tool_default: usize) -> usize {
+        self.tool_limits.get(tool).copied().unwrap_or(tool_default.min(self.max_result_rows)).max(1)
+    }
+
+    // masked_columns holds lowercase names; SQLite matches table and column names case-insensitively
+    fn is_masked(&self, table: &str, column: &str) -> bool {
+        let column = column.to_ascii_lowercase();
+        self.masked_columns.get(&table.to_ascii_lowercase()).is_some_and(|cols| cols.contains(&column))
+    }
+
+    // Row keys carry whatever case the query used (SELECT DATA ...), so each key is compared lowercased
+    fn mask_row(&self, table: &str, row: &mut serde_json::Map<String, Value>) {
+        let Some(cols) = self.masked_columns.get(&table.to_ascii_lowercase()) else { return };
+        for (key, v) in row.iter_mut() {
+            if cols.contains(&key.to_ascii_lowercase()) { *v = Value::from("***"); }
+        }
+    }
+
+    // Notebook tools build their own items; snippet comes from data, so it follows data's mask
+    fn mask_notebook(&self, item: &mut Value) {
+        let Some(obj) = item.as_object_mut() else { return };
+        for (key, col) in [("title", "title"), ("data", "data"), ("snippet", "data")] {
+            if self.is_masked("notebooks", col) {
+                if let Some(v) = obj.get_mut(key) { *v = Value::from("***"); }
+            }
+        }
+    }
+
+    // { id, title, snippet, char_count } from a notebook list query, plus data when the query selected it; masked
+    fn notebook_item(&self, r: &SqliteRow) -> Value {
+        let id: i64 = r.try_get("id").unwrap_or_default();
+        let title: String = r.try_get("title").unwrap_or_default();
+        let snippet: String = r.try_get("snippet").unwrap_or_default();
+        let char_count: i64 = r.try_get("char_count").unwrap_or_default();
+        let mut item = serde_json::json!({"id": id, "title": title, "snippet": snippet, "char_count": char_count});
+        if r.columns().iter().any(|c| c.name() == "data") { item["data"] = Value::from(r.try_get::<String, _>("data").unwrap_or_default()); }
+        self.mask_notebook(&mut item);
+        item
+    }
+
+    fn check_params(&self, count: usize) -> std::result::Result<(), ErrorData> {
+        if count > self.max_params {
+            return Err(ErrorData::invalid_params(
+                format!("Too many bind parameters: {} (limit {}, set by WARP_SQLITE_MAX_PARAMS)", count, self.max_params),
+                Some(serde_json::json!({ "param_count": count, "max_params": self.max_params })),
+            ));
+        }
+        Ok(())
+    }
+}
+
+const IDEMPOTENCY_CACHE_SIZE: usize = 1_000;
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(5 * 60);
+
+fn unix_secs() -> i64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+// result_json of a key whose write is still running
+const PENDING_RESULT: &str = "";
+
+enum Claim { Replay(CallToolResult), Owned(KeyClaim) }
+
+// An idempotency key reserved for the running write. complete() stores the result; dropped without it
+// (the write failed or the call was cancelled) the reservation is released so a retry can run.
+struct KeyClaim { pool: Pool<Sqlite>, cache: Arc<Mutex<LruCache<String, (Instant, CallToolResult)>>>, key: Option<String>, persisted: bool }
+impl KeyClaim {
+    async fn complete(mut self, res: &CallToolResult) {
+        let Some(key) = self.key.take() else { return };
+        self.cache.lock().unwrap().put(key.clone(), (Instant::now(), res.clone()));
+        if !self.persisted { return; }
+        // Best-effort: the write already happened, so a failure to persist the result must not fail the call
+        let text = res.content.first().and_then(|c| c.as_text()).map(|t| t.text.clone()).unwrap_or_default();
+        let _ = sqlx::query("UPDATE idempotency_keys SET result_json = ?2 WHERE key = ?1")
+            .bind(key)
+            .bind(text)
+            .execute(&self.pool).await;
+    }
+}
+impl Drop for KeyClaim {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take().filter(|_| self.persisted) else { return };
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let _ = sqlx::query("DELETE FROM idempotency_keys WHERE key = ?1 AND result_json = ?2")
+                .bind(key)
+                .bind(PENDING_RESULT)
+                .execute(&pool).await;
+        });
+    }
+}
+
+// Replays a stored result, or reserves the key in idempotency_keys before the write runs, so a retry that
+// arrives while the first call is still running gets a conflict instead of writing twice. Without the table
+// (read-only database) keys are only remembered in memory.
+async fn claim_key(state: &AppState, key: Option<String>) -> std::result::Result<Claim, ErrorData> {
+    let mut claim = KeyClaim { pool: state.pool.clone(), cache: state.request_cache.clone(), key: None, persisted: false };
+    let Some(key) = key else { return Ok(Claim::Owned(claim)) };
+    {
+        let mut cache = state.request_cache.lock().unwrap();
+        match cache.get(&key) {
+            Some((at, res)) if at.elapsed() < IDEMPOTENCY_TTL => return Ok(Claim::Replay(res.clone())),
+            Some(_) => { cache.pop(&key); }
+            None => {}
+        }
+    }
+    let now = unix_secs();
+    let expired_before = now - IDEMPOTENCY_TTL.as_secs() as i64;
+    // Expired rows, including reservations left by a crash, can be taken over
+    let reserved = sqlx::query(
+        "INSERT INTO idempotency_keys (key, result_json, created_at) VALUES (?1, ?2, ?3) \
+         ON CONFLICT (key) DO UPDATE SET result_json = excluded.result_json, created_at = excluded.created_at \
+         WHERE idempotency_keys.created_at <= ?4")
+        .bind(&key)
+        .bind(PENDING_RESULT)
+        .bind(now)
+        .bind(expired_before)
+        .execute(&state.pool).await;
+    match reserved {
+        Ok(r) if r.rows_affected() > 0 => { claim.persisted = true; }
+        Ok(_) => {
+            let text: Option<String> = sqlx::query_scalar("SELECT result_json FROM idempotency_keys WHERE key = ?1")
+                .bind(&key)
+                .fetch_optional(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            match text {
+                Some(t) if t != PENDING_RESULT => return Ok(Claim::Replay(CallToolResult::success(vec![Content::text(t)]))),
+                _ => return Err(ErrorData::invalid_params(
+                    "A call with this idempotency_key is still running; retry after it finishes".to_string(),
+                    Some(serde_json::json!({ "kind": "in_progress" })),
+                )),
+            }
+        }
+        Err(_) => {}
+    }
+    claim.key = Some(key);
+    Ok(Claim::Owned(claim))
+}
+
+// Created at startup; best-effort so a read-only database still serves everything else
+async fn ensure_idempotency_table(pool: &Pool<Sqlite>) {
+    if let Err(e) = sqlx::query("CREATE TABLE IF NOT EXISTS idempotency_keys (key TEXT PRIMARY KEY, result_json TEXT NOT NULL, created_at INTEGER NOT NULL)")
+        .execute(pool).await
+    {
+        eprintln!("warning: idempotency keys will not survive a restart: {}", e);
+    }
+}
+
+// Deletes expired idempotency keys once per TTL
+fn spawn_idempotency_pruner(pool: Pool<Sqlite>) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(IDEMPOTENCY_TTL);
+        loop {
+            tick.tick().await;
+            let _ = sqlx::query("DELETE FROM idempotency_keys WHERE created_at <= ?1")
+                .bind(unix_secs() - IDEMPOTENCY_TTL.as_secs() as i64)
+                .execute(&pool).await;
+        }
+    });
+}
+
+// Always denied to the CRUD tools, in addition to DENIED_TABLES; sqlite_schema and sqlite_temp_schema are
+// SQLite's newer names for the first two. Lowercase, like every table list in AppState
+const DEFAULT_DENIED_TABLES: &[&str] = &["sqlite_master", "sqlite_temp_master", "sqlite_schema", "sqlite_temp_schema", "sqlite_stat1", "idempotency_keys"];
+// Warp's own tables; sqlite_truncate refuses them unless force_protected is set
+const PROTECTED_TABLES: &[&str] = &["notebooks", "active_mcp_servers", "mcp_environment_variables", "notebook_tags", "idempotency_keys"];
+
 fn is_valid_ident(re: &Regex, s: &str) -> bool { re.is_match(s) }
 
+// "table.column" with both parts valid identifiers
+fn split_qualified<'a>(re: &Regex, s: &'a str) -> Option<(&'a str, &'a str)> {
+    s.split_once('.').filter(|(t, c)| is_valid_ident(re, t) && is_valid_ident(re, c))
+}
+
+// schema.table or table, each part checked against ident_re; returns (schema, table)
+fn parse_qualified_ident(re: &Regex, s: &str) -> std::result::Result<(Option<String>, String), ErrorData> {
+    // Each call may run on a different pooled connection, and temp tables only live on the one that made them
+    if s.split_once('.').is_some_and(|(schema, _)| schema.eq_ignore_ascii_case("temp")) {
+        return Err(ErrorData::invalid_params("The temp schema isn't supported: temp tables don't persist between calls".to_string(), None));
+    }
+    let parsed = match s.split_once('.') {
+        Some((schema, table)) if is_valid_ident(re, schema) && is_valid_ident(re, table) => Some((Some(schema.to_string()), table.to_string())),
+        None if is_valid_ident(re, s) => Some((None, s.to_string())),
+        _ => None,
+    };
+    parsed.ok_or_else(|| ErrorData::invalid_params("Invalid table name".to_string(), None))
+}
+
+// Table-level access control for the generic CRUD tools. SQLite table names are case-insensitive, so the lists
+// hold lowercase names and the table is lowercased before the lookup
+fn check_table_access(state: &AppState, table: &str) -> std::result::Result<(), ErrorData> {
+    let table = table.to_ascii_lowercase();
+    if state.denied_tables.contains(&table) { return Err(ErrorData::invalid_params("Table access denied".to_string(), None)); }
+    if let Some(allowed) = &state.allowed_tables {
+        if !allowed.contains(&table) { return Err(ErrorData::invalid_params("Table not in allowlist".to_string(), None)); }
+    }
+    Ok(())
+}
+
+// Tools that move rows between tables can't mask what they copy, so they refuse masked source columns. With None
+// (SELECT *, a rename, triggers copying every column) any masked column of the table counts
+fn check_not_masked(state: &AppState, table: &str, columns: Option<&[&str]>) -> std::result::Result<(), ErrorData> {
+    let masked = match columns {
+        Some(cols) => cols.iter().find(|c| state.is_masked(table, c)).map(|c| c.to_string()),
+        None => state.masked_columns.get(&table.to_ascii_lowercase()).and_then(|cols| cols.first().cloned()),
+    };
+    match masked {
+        Some(c) => Err(ErrorData::invalid_params(format!("Column is masked: {}.{}", table, c), None)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct InsertInput {
+    #[schemars(description = "Table name, optionally schema-qualified (main.notes)", example = &"notebooks")]
+    table: String,
+    #[schemars(description = "Column name -> value for the new row; arrays and objects are stored as JSON text")]
+    values: serde_json::Map<String, Value>,
+    #[schemars(description = "Columns whose values are normalized to YYYY-MM-DD HH:MM:SS before insert")]
+    date_columns: Option<Vec<String>>,
+    #[schemars(description = "Columns whose string values must be valid JSON")]
+    json_validate_columns: Option<Vec<String>>,
+    #[schemars(description = "Columns stored as JSONB via jsonb(?) (SQLite 3.45+)")]
+    jsonb_columns: Option<Vec<String>>,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of inserting again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
 #[derive(Deserialize, JsonSchema)]
-struct InsertInput { table: String, values: serde_json::Map<String, Value> }
+struct UpsertOrFailInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column name -> value for the new row")]
+    values: serde_json::Map<String, Value>,
+    #[schemars(description = "Columns of a PRIMARY KEY or UNIQUE constraint; a clash fails with \"Row already exists\"")]
+    conflict_columns: Vec<String>,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of inserting again, so a retry doesn't fail with \"Row already exists\"")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
 #[derive(Deserialize, JsonSchema)]
+struct InsertDefaultRowInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema, Default)]
 struct SelectInput {
+    #[schemars(description = "Table name, optionally schema-qualified (main.notes)", example = &"notebooks")]
     table: String,
+    #[schemars(description = "Columns to return; all columns when omitted. Use table.column with joins")]
     columns: Option<Vec<String>>,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword; ? placeholders are filled from params in order. \
+                              Put ORDER BY in order_by and LIMIT in limit, not here", example = &"title LIKE ? AND id > ?")]
     #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
     params: Option<Vec<Value>>,
+    #[schemars(description = "ORDER BY fragment without the keywords", example = &"id DESC")]
+    order_by: Option<String>,
+    #[schemars(description = "Maximum rows to return; capped by the server's row limit")]
+    limit: Option<i64>,
+    #[schemars(description = "Rows to skip before returning results")]
+    offset: Option<i64>,
+    #[schemars(description = "Columns returned through datetime() as YYYY-MM-DD HH:MM:SS")]
+    date_columns: Option<Vec<String>>,
+    #[schemars(description = "Columns returned as strings to preserve numeric precision")]
+    decimal_columns: Option<Vec<String>>,
+    #[schemars(description = "Order by rowid (or the primary key) when order_by is absent; defaults to WARP_SQLITE_STABLE_ORDER")]
+    stable: Option<bool>,
+    #[schemars(description = "\"include\" (default) keeps null cells; \"omit\" drops them from each row object")]
+    null_handling: Option<String>,
+    #[schemars(description = "Caller-chosen id so the query can be stopped with sqlite_cancel while it runs")]
+    query_id: Option<String>,
+    #[schemars(description = "Tables to join; columns must then be given as table.column and results are keyed the same way")]
+    joins: Option<Vec<JoinSpec>>,
+    #[schemars(description = "Gzip the result past WARP_SQLITE_COMPRESS_THRESHOLD; sqlite_select only")]
+    compress: Option<bool>,
+    #[schemars(description = "Composite key lookup rendered as (col1, col2) IN ((?, ?), ...) and ANDed with where")]
+    tuple_in: Option<TupleIn>,
+    #[schemars(description = "Page through the result: return up to chunk_rows rows plus a next_cursor for the following page; \
+                              sqlite_select only, not with compress. limit then caps the rows across all pages")]
+    chunked: Option<bool>,
+    #[schemars(description = "Rows per page with chunked (default 1000, at most the row limit)")]
+    chunk_rows: Option<usize>,
+    #[schemars(description = "next_cursor from the previous page of a chunked select; omit for the first page")]
+    cursor: Option<String>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct TupleIn {
+    #[schemars(description = "Key columns; with joins, as table.column")]
+    columns: Vec<String>,
+    #[schemars(description = "Key tuples, each with one value per column; an empty list matches no rows", example = &serde_json::json!([[1, "x"], [2, "y"]]))]
+    rows: Vec<Vec<Value>>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SelectBetweenInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column compared with low and high")]
+    column: String,
+    #[schemars(description = "Inclusive lower bound")]
+    low: Value,
+    #[schemars(description = "Inclusive upper bound")]
+    high: Value,
+    #[schemars(description = "Columns to return; all columns when omitted")]
+    columns: Option<Vec<String>>,
+    #[schemars(description = "Maximum rows to return")]
+    limit: Option<i64>,
+    #[schemars(description = "ORDER BY fragment without the keywords")]
     order_by: Option<String>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SelectLikeInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column matched against pattern")]
+    column: String,
+    #[schemars(description = "LIKE pattern: % matches any run of characters, _ exactly one", example = &"%invoice%")]
+    pattern: String,
+    #[schemars(description = "Match case exactly; false (default) ignores case")]
+    #[serde(default)] case_sensitive: bool,
+    #[schemars(description = "Columns to return; all columns when omitted")]
+    columns: Option<Vec<String>>,
+    #[schemars(description = "Maximum rows to return")]
+    limit: Option<i64>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SelectNullInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column tested for NULL")]
+    column: String,
+    #[schemars(description = "sqlite_select_null only; defaults to true (sqlite_select_not_null always matches IS NOT NULL)")]
+    is_null: Option<bool>,
+    #[schemars(description = "Columns to return; all columns when omitted")]
+    columns: Option<Vec<String>>,
+    #[schemars(description = "Maximum rows to return; total_count ignores it")]
+    limit: Option<i64>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct HasColumnInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column name to look for")]
+    column: String,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SelectInInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column matched against values")]
+    column: String,
+    #[schemars(description = "Values to match; any length, bound as parameters")]
+    values: Vec<Value>,
+    #[schemars(description = "Columns to return; all columns when omitted")]
+    columns: Option<Vec<String>>,
+    #[schemars(description = "Maximum rows to return across all values")]
     limit: Option<i64>,
+    #[schemars(description = "Rows to skip across all values")]
     offset: Option<i64>,
 }
 #[derive(Deserialize, JsonSchema)]
+struct JoinSpec {
+    #[schemars(description = "inner (default) or left")]
+    #[serde(rename = "type")] join_type: Option<String>,
+    #[schemars(description = "Table to join")]
+    table: String,
+    #[schemars(description = "Column of the base table, or table.column of an earlier join", example = &"id")]
+    on_left: String,
+    #[schemars(description = "Column of this join's table matched against on_left", example = &"notebook_id")]
+    on_right: String,
+}
+#[derive(Deserialize, JsonSchema)]
+struct MultiSelectInput {
+    #[schemars(description = "Selects to run, each with the same fields as sqlite_select")]
+    queries: Vec<SelectInput>,
+}
+#[derive(Deserialize, JsonSchema)]
 struct UpdateInput {
+    #[schemars(description = "Table name, optionally schema-qualified (main.notes)")]
+    table: String,
+    #[schemars(description = "Column name -> new value")]
+    set: serde_json::Map<String, Value>,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword; ? placeholders are filled from params. \
+                              Omitting it updates every row", example = &"id = ?")]
+    #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
+    params: Option<Vec<Value>>,
+    #[schemars(description = "Columns whose values are normalized to YYYY-MM-DD HH:MM:SS")]
+    date_columns: Option<Vec<String>>,
+    #[schemars(description = "Columns whose string values must be valid JSON")]
+    json_validate_columns: Option<Vec<String>>,
+    #[schemars(description = "Columns stored as JSONB via jsonb(?) (SQLite 3.45+)")]
+    jsonb_columns: Option<Vec<String>>,
+    #[schemars(description = "Roll back and fail if more rows than this would change")]
+    max_affected: Option<i64>,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of updating again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct CompareAndSetInput {
+    #[schemars(description = "Table name")]
     table: String,
+    #[schemars(description = "Column name -> new value")]
     set: serde_json::Map<String, Value>,
+    #[schemars(description = "Current values the row must still hold; each becomes AND col IS ?")]
+    expect: serde_json::Map<String, Value>,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword selecting the row", example = &"id = ?")]
+    #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
+    params: Option<Vec<Value>>,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of updating again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct DeleteInput {
+    #[schemars(description = "Table name, optionally schema-qualified (main.notes)")]
+    table: String,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword; ? placeholders are filled from params. \
+                              Omitting it deletes every row", example = &"id = ?")]
+    #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
+    params: Option<Vec<Value>>,
+    #[schemars(description = "Roll back and fail if more rows than this would be deleted")]
+    max_affected: Option<i64>,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of deleting again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Also return the rowids (or single-column primary key) of the deleted rows")]
+    #[serde(default)] return_ids: bool,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct MatchCountInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword", example = &"status = ?")]
+    #[serde(rename = "where")] r#where: String,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
+    params: Option<Vec<Value>>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct TruncateInput {
+    #[schemars(description = "Table to empty")]
+    table: String,
+    #[schemars(description = "Also reset the table's AUTOINCREMENT counter")]
+    #[serde(default)] reset_sequence: bool,
+    #[schemars(description = "Required to truncate Warp's own tables")]
+    #[serde(default)] force_protected: bool,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct WhereCondition {
+    #[schemars(description = "Column name")]
+    column: String,
+    #[schemars(description = "=, !=, <, <=, >, >=, LIKE, NOT LIKE, IS NULL or IS NOT NULL")]
+    operator: String,
+    #[schemars(description = "Value compared with the column; ignored for IS NULL / IS NOT NULL")]
+    #[serde(default)] value: Value,
+}
+#[derive(Deserialize, JsonSchema)]
+struct WhereBuilderInput {
+    #[schemars(description = "Conditions to combine; at least one")]
+    conditions: Vec<WhereCondition>,
+    #[schemars(description = "AND (default) or OR")]
+    combinator: Option<String>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct DistinctValuesInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column whose distinct values are listed")]
+    column: String,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword")]
+    #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
+    params: Option<Vec<Value>>,
+    #[schemars(description = "Maximum values to return; total counts them all")]
+    limit: Option<i64>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct ValueCountsInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column whose values are counted")]
+    column: String,
+    #[schemars(description = "Maximum values to return")]
+    limit: Option<i64>,
+    #[schemars(description = "Most common values first instead of ordering by value")]
+    #[serde(default)] order_by_count: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SampleInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Number of rows to sample")]
+    count: i64,
+    #[schemars(description = "Columns to return; all columns when omitted")]
+    columns: Option<Vec<String>>,
+    #[schemars(description = "Pick random rowids instead of ORDER BY RANDOM(); faster on large tables but may return fewer rows")]
+    fast: Option<bool>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct RowDiffInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Column identifying the rows, usually the primary key")]
+    id_column: String,
+    #[schemars(description = "id_column value of the first row")]
+    id_a: Value,
+    #[schemars(description = "id_column value of the second row")]
+    id_b: Value,
+}
+#[derive(Deserialize, JsonSchema)]
+struct IncrementInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Numeric column to increment")]
+    column: String,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword", example = &"id = ?")]
+    #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
+    params: Option<Vec<Value>>,
+    #[schemars(description = "Amount to add (default 1; negative to decrement)")]
+    by: Option<i64>,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of incrementing again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct InsertFromSelectInput {
+    #[schemars(description = "Table receiving the rows")]
+    dest_table: String,
+    #[schemars(description = "Table the rows are copied from")]
+    source_table: String,
+    #[schemars(description = "Columns copied by name, present in both tables; when omitted SELECT * fills dest_table by position")]
+    columns: Option<Vec<String>>,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword, applied to source_table")]
+    #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
+    params: Option<Vec<Value>>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct CopyRowsInput {
+    #[schemars(description = "Table the rows are copied from")]
+    source_table: String,
+    #[schemars(description = "Table receiving the rows")]
+    dest_table: String,
+    #[schemars(description = "Source column -> destination column")]
+    column_map: HashMap<String, String>,
+    #[schemars(description = "SQL WHERE fragment without the WHERE keyword, applied to source_table")]
     #[serde(rename = "where")] r#where: Option<String>,
+    #[schemars(description = "Values bound to the ? placeholders in where, in order")]
     params: Option<Vec<Value>>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct MergeTablesInput {
+    #[schemars(description = "Table the rows are copied from")]
+    source_table: String,
+    #[schemars(description = "Table receiving the rows")]
+    dest_table: String,
+    #[schemars(description = "ignore (default), replace, abort, fail or rollback")]
+    conflict_action: Option<String>,
+    #[schemars(description = "Check that the column lists match and return the INSERT ... SELECT without copying any rows")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SwapTablesInput {
+    #[schemars(description = "First table")]
+    table_a: String,
+    #[schemars(description = "Second table")]
+    table_b: String,
+    #[schemars(description = "Return the three ALTER TABLE ... RENAME statements without renaming anything")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct BatchDeleteInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Key column matched against pk_values")]
+    pk_column: String,
+    #[schemars(description = "Key values of the rows to delete")]
+    pk_values: Vec<Value>,
+    #[schemars(description = "Transaction mode: deferred (default), immediate or exclusive")]
+    #[serde(default)] mode: Option<String>,
+    #[schemars(description = "Run each key in its own savepoint so a key that fails (e.g. on a constraint) is rolled back and reported \
+                              in results while the others are deleted; default false rolls back the whole batch")]
+    #[serde(default)] continue_on_error: bool,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of deleting again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
 }
 #[derive(Deserialize, JsonSchema)]
-struct DeleteInput { table: String, #[serde(rename = "where")] r#where: Option<String>, params: Option<Vec<Value>> }
+struct UpdateWhereInInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Key column matched against pk_values")]
+    pk_column: String,
+    #[schemars(description = "Key values of the rows to update")]
+    pk_values: Vec<Value>,
+    #[schemars(description = "Column name -> new value")]
+    set: serde_json::Map<String, Value>,
+    #[schemars(description = "Transaction mode: deferred (default), immediate or exclusive")]
+    #[serde(default)] mode: Option<String>,
+    #[schemars(description = "Run each key in its own savepoint so a key that fails (e.g. on a constraint) is rolled back and reported \
+                              in results while the others are updated; default false rolls back the whole batch")]
+    #[serde(default)] continue_on_error: bool,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of updating again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
 
 // Domain-specific tool inputs
 #[derive(Deserialize, JsonSchema)]
-struct McpRegisterInput { mcp_server_uuid: String }
+struct McpRegisterInput {
+    #[schemars(description = "UUID of the MCP server to register")]
+    mcp_server_uuid: String,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
 #[derive(Deserialize, JsonSchema)]
-struct McpUnregisterInput { mcp_server_uuid: String }
+struct McpUnregisterInput {
+    #[schemars(description = "UUID of the MCP server to remove")]
+    mcp_server_uuid: String,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
 #[derive(Deserialize, JsonSchema)]
-struct McpSetEnvInput { mcp_server_uuid: String, env: Value }
+struct McpSetEnvInput {
+    #[schemars(description = "UUID of the MCP server whose environment is replaced")]
+    mcp_server_uuid: String,
+    #[schemars(description = "Environment variables as a JSON object of name -> value", example = &serde_json::json!({ "API_URL": "http://localhost:8080" }))]
+    env: Value,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
 #[derive(Deserialize, JsonSchema)]
-struct McpGetEnvInput { mcp_server_uuid: String }
+struct McpGetEnvInput {
+    #[schemars(description = "UUID of the MCP server whose environment is read")]
+    mcp_server_uuid: String,
+}
 
 #[derive(Deserialize, JsonSchema)]
-struct NotebookCreateInput { title: Option<String>, body: String }
+struct NotebookCreateInput {
+    #[schemars(description = "Notebook title; empty when omitted")]
+    title: Option<String>,
+    #[schemars(description = "Notebook content, stored in notebooks.data")]
+    body: String,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of creating another notebook")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookAppendInput {
+    #[schemars(description = "Notebook to append to")]
+    id: i64,
+    #[schemars(description = "Text appended to the notebook content")]
+    delta: String,
+    #[schemars(description = "Inserted before delta only when the notebook already has content")]
+    separator: Option<String>,
+    #[schemars(description = "Add a trailing newline to delta if it lacks one")]
+    ensure_trailing_newline: Option<bool>,
+    #[schemars(description = "Repeating a call with the same key returns the first result instead of appending again")]
+    idempotency_key: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookDeleteInput {
+    #[schemars(description = "Notebook to delete")]
+    id: i64,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookBulkDeleteInput {
+    #[schemars(description = "Notebooks to delete, 1 to 100 ids; their notebook_versions rows go too")]
+    ids: Vec<i64>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookMergeInput {
+    #[schemars(description = "Notebook whose body is appended")]
+    source_id: i64,
+    #[schemars(description = "Notebook receiving the body")]
+    target_id: i64,
+    #[schemars(description = "Inserted between the bodies only when the target is non-empty")]
+    separator: Option<String>,
+    #[schemars(description = "Delete the source notebook after merging")]
+    delete_source: Option<bool>,
+    #[schemars(description = "Return the UPDATE (and DELETE, with delete_source) without changing either notebook")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookListInput {
+    #[schemars(description = "Substring matched against title and body")]
+    query: Option<String>,
+    #[schemars(description = "Maximum notebooks to return (default 50, at most 500 unless tool_limits or MAX_RESULT_ROWS say otherwise)")]
+    limit: Option<i64>,
+    #[schemars(description = "Notebooks to skip, newest first (default 0)")]
+    offset: Option<i64>,
+    #[schemars(description = "Characters of body in each snippet (default 200, at most 2000)")]
+    snippet_length: Option<i64>,
+    #[schemars(description = "With a query, center the snippet on the first match in the body instead of taking its start")]
+    snippet_from_match: Option<bool>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookRecentInput {
+    #[schemars(description = "Maximum notebooks to return (default 10, at most 100 unless tool_limits or MAX_RESULT_ROWS say otherwise)")]
+    limit: Option<i64>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookWordSearchInput {
+    #[schemars(description = "Whitespace-separated words, matched case-insensitively in title or body")]
+    query: String,
+    #[schemars(description = "Require every word instead of any")]
+    #[serde(default)] match_all: bool,
+    #[schemars(description = "Maximum notebooks to return (default 50, at most 500 unless tool_limits or MAX_RESULT_ROWS say otherwise)")]
+    limit: Option<i64>,
+    #[schemars(description = "Matching notebooks to skip, newest first (default 0)")]
+    offset: Option<i64>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookListByIdsInput {
+    #[schemars(description = "Notebooks to fetch; duplicates and unknown ids are skipped")]
+    ids: Vec<i64>,
+    #[schemars(description = "Also return each notebook's full content as data")]
+    #[serde(default)] include_body: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct NotebookGetInput {
+    #[schemars(description = "Notebook to fetch")]
+    id: i64,
+    #[schemars(description = "Gzip the result past WARP_SQLITE_COMPRESS_THRESHOLD")]
+    compress: Option<bool>,
+}
 #[derive(Deserialize, JsonSchema)]
-struct NotebookAppendInput { id: i64, delta: String }
+struct NotebookTagAddInput {
+    #[schemars(description = "Notebook to tag")]
+    id: i64,
+    #[schemars(description = "Tag to add, 1 to 99 characters")]
+    tag: String,
+    #[schemars(description = "Validate the tag and return the INSERT without tagging the notebook")]
+    #[serde(default)] dry_run: bool,
+}
 #[derive(Deserialize, JsonSchema)]
-struct NotebookDeleteInput { id: i64 }
+struct NotebookTagRemoveInput {
+    #[schemars(description = "Notebook to untag")]
+    id: i64,
+    #[schemars(description = "Tag to remove")]
+    tag: String,
+    #[schemars(description = "Return the DELETE without untagging the notebook")]
+    #[serde(default)] dry_run: bool,
+}
 #[derive(Deserialize, JsonSchema)]
-struct NotebookListInput { query: Option<String>, limit: Option<i64>, offset: Option<i64> }
+struct NotebookGetTagsInput {
+    #[schemars(description = "Notebook whose tags are listed")]
+    id: i64,
+}
 #[derive(Deserialize, JsonSchema)]
-struct NotebookGetInput { id: i64 }
+struct NotebookListByTagInput {
+    #[schemars(description = "Tag to match exactly")]
+    tag: String,
+    #[schemars(description = "Maximum notebooks to return (default 50, at most 500 unless tool_limits or MAX_RESULT_ROWS say otherwise)")]
+    limit: Option<i64>,
+    #[schemars(description = "Tagged notebooks to skip, newest first (default 0)")]
+    offset: Option<i64>,
+}
 
-#[derive(Deserialize)]
-struct FileConfig { database: DatabaseConfig }
-#[derive(Deserialize)]
-struct DatabaseConfig { url: String }
+// Maintenance tool inputs
+#[derive(Deserialize, JsonSchema)]
+struct RebuildFtsInput {
+    #[schemars(description = "FTS5 virtual table to rebuild from its content")]
+    fts_table: String,
+    #[schemars(description = "Check that fts_table is FTS5 and return the 'rebuild' command without running it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct ReindexInput {
+    #[schemars(description = "Index or table to rebuild; every index when omitted")]
+    #[serde(alias = "target")] index_or_table: Option<String>,
+    #[schemars(description = "Also refresh planner statistics with ANALYZE for the same target")]
+    #[serde(default)] analyze: bool,
+    #[schemars(description = "Return the REINDEX (and ANALYZE) statements without rebuilding anything")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "sqlcipher"), allow(dead_code))]
+struct ExportEncryptedInput {
+    #[schemars(description = "File to write the encrypted copy to; must not exist yet")]
+    destination_path: String,
+    #[schemars(description = "Non-empty SQLCipher key for the copy")]
+    passphrase: String,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SetUserVersionInput {
+    #[schemars(description = "New PRAGMA user_version, 0 or greater")]
+    version: i32,
+    #[schemars(description = "Return the PRAGMA without changing the stored version")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SetApplicationIdInput {
+    #[schemars(description = "New PRAGMA application_id")]
+    application_id: i32,
+    #[schemars(description = "Return the PRAGMA without changing the file's application_id")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct SetupHistoryTableInput {
+    #[schemars(description = "Table whose changes are recorded")]
+    table: String,
+    #[schemars(description = "Appended to table to name the history table (default _history)")]
+    history_table_suffix: Option<String>,
+    #[schemars(description = "Return the generated SQL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct ColumnDef {
+    #[schemars(description = "Column name")]
+    name: String,
+    #[schemars(description = "INTEGER, REAL, TEXT, BLOB or NUMERIC")]
+    #[serde(rename = "type")] col_type: String,
+    #[schemars(description = "Add NOT NULL")]
+    #[serde(default)] not_null: bool,
+    #[schemars(description = "Add PRIMARY KEY")]
+    #[serde(default)] primary_key: bool,
+    #[schemars(description = "Add UNIQUE")]
+    #[serde(default)] unique: bool,
+    #[schemars(description = "Scalar DEFAULT value: string, number, boolean or null")]
+    default: Option<Value>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct DropTableInput {
+    #[schemars(description = "Table to drop")]
+    name: String,
+    #[schemars(description = "Succeed without doing anything when the table doesn't exist (default false)")]
+    if_exists: Option<bool>,
+    #[schemars(description = "Must be true; guards against dropping a table by accident")]
+    #[serde(default)] confirm: bool,
+    #[schemars(description = "Return the DROP TABLE without dropping anything; confirm isn't needed")]
+    #[serde(default)] dry_run: bool,
+}
+#[derive(Deserialize, JsonSchema)]
+struct CreateTableInput {
+    #[schemars(description = "Name of the new table")]
+    name: String,
+    #[schemars(description = "Column definitions, in order")]
+    columns: Vec<ColumnDef>,
+    #[schemars(description = "Succeed without doing anything when the table already exists")]
+    #[serde(default)] if_not_exists: bool,
+    #[schemars(description = "Return the generated DDL without executing it")]
+    #[serde(default)] dry_run: bool,
+}
 
-fn load_db_url() -> String {
-    if let Ok(v) = std::env::var("DATABASE_URL") { return v; }
-    // Try ./config.toml and alongside the executable
-    let candidates = [
-        std::env::current_dir().ok().map(|p| p.join("config.toml")),
-        std::env::current_exe().ok().and_then(|p| p.parent().map(|d| d.join("config.toml"))),
-    ];
-    for opt in candidates {
-        if let Some(path) = opt {
-            if let Ok(text) = std::fs::read_to_string(&path) {
-                if let Ok(cfg) = toml::from_str::<FileConfig>(&text) {
-                    return cfg.database.url;
-                }
-            }
-        }
+// Diagnostic tool inputs
+#[derive(Deserialize, JsonSchema)]
+struct CancelInput {
+    #[schemars(description = "query_id given to the running select; the most recent running query when omitted")]
+    query_id: Option<String>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct ComputeChecksumInput {
+    #[schemars(description = "Table name")]
+    table: String,
+    #[schemars(description = "Columns included in the checksum; all columns when omitted")]
+    columns: Option<Vec<String>>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct ForeignKeysInput {
+    #[schemars(description = "Table whose foreign keys are listed")]
+    table: String,
+}
+#[derive(Deserialize, JsonSchema)]
+struct ForeignKeyCheckInput {
+    #[schemars(description = "Table to check; every table when omitted")]
+    table: Option<String>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct WalInfoInput {
+    #[schemars(description = "PASSIVE (default), FULL, RESTART or TRUNCATE")]
+    checkpoint_mode: Option<String>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct DumpSchemaInput {
+    #[schemars(description = "Prefix each object with DROP ... IF EXISTS")]
+    with_drops: Option<bool>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct GetViewDefinitionInput {
+    #[schemars(description = "View name")]
+    name: String,
+}
+#[derive(Deserialize, JsonSchema)]
+struct ListTriggersInput {
+    #[schemars(description = "Only triggers on this table; every allowed table when omitted")]
+    table: Option<String>,
+}
+#[derive(Deserialize, JsonSchema)]
+struct DumpDataInput {
+    #[schemars(description = "Tables to dump; every allowed table when omitted")]
+    tables: Option<Vec<String>>,
+}
+
+// Tool output schemas, declared on each route for clients; results themselves are still built with json!.
+// Write tools can answer with a dry run instead, so their fields are optional and include DryRunFields.
+// Tools taking compress likewise may answer with just CompressedFields.
+#[derive(Serialize, JsonSchema)]
+struct DryRunFields { sql: Option<String>, param_count: Option<u64>, dry_run: Option<bool> }
+#[derive(Serialize, JsonSchema)]
+struct CompressedFields { encoding: Option<String>, data: Option<String>, #[serde(rename = "_compressed")] compressed: Option<bool> }
+#[derive(Serialize, JsonSchema)]
+struct InsertOutput { last_insert_rowid: Option<i64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct RowsOutput { rows: Vec<serde_json::Map<String, Value>>, row_count: usize, truncated: bool, limit_applied: Option<i64> }
+#[derive(Serialize, JsonSchema)]
+struct SelectOutput {
+    rows: Option<Vec<serde_json::Map<String, Value>>>,
+    row_count: Option<usize>,
+    truncated: Option<bool>,
+    limit_applied: Option<i64>,
+    // Chunked selects only: where the next page starts (null on the last page) and rows returned so far
+    next_cursor: Option<String>,
+    total_row_count: Option<usize>,
+    #[serde(flatten)] compressed: CompressedFields,
+}
+#[derive(Serialize, JsonSchema)]
+struct NullRowsOutput { rows: Vec<serde_json::Map<String, Value>>, row_count: usize, truncated: bool, limit_applied: Option<i64>, total_count: i64 }
+#[derive(Serialize, JsonSchema)]
+struct EstimateStep { detail: String, access: String, index: Option<String>, estimated_rows: Option<i64> }
+#[derive(Serialize, JsonSchema)]
+struct EstimateOutput { uses_index: bool, full_scans: usize, steps: Vec<EstimateStep> }
+#[derive(Serialize, JsonSchema)]
+struct MultiSelectResult {
+    rows: Option<Vec<serde_json::Map<String, Value>>>,
+    row_count: Option<usize>,
+    truncated: Option<bool>,
+    limit_applied: Option<i64>,
+    error: Option<String>,
+}
+#[derive(Serialize, JsonSchema)]
+struct MultiSelectOutput { results: Vec<MultiSelectResult> }
+#[derive(Serialize, JsonSchema)]
+struct AffectedRowCountOutput { affected_row_count: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct CompareAndSetOutput { updated: Option<bool>, affected_row_count: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct RowsAffectedOutput { rows_affected: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct RowsDeletedOutput { rows_deleted: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+// One pk value of a continue_on_error batch: rows_affected when it applied, error when it was rolled back
+#[derive(Serialize, JsonSchema)]
+struct BatchItemResult { pk_value: Value, ok: bool, rows_affected: Option<u64>, error: Option<String> }
+#[derive(Serialize, JsonSchema)]
+struct BatchDeleteOutput { rows_deleted: Option<u64>, results: Option<Vec<BatchItemResult>>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct UpdateWhereInOutput { rows_affected: Option<u64>, results: Option<Vec<BatchItemResult>>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct DeleteOutput { affected_row_count: Option<u64>, deleted_ids: Option<Vec<Value>>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct NotebookBulkDeleteOutput { rows_deleted: Option<u64>, deleted_ids: Option<Vec<i64>>, versions_deleted: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct RowsInsertedOutput { rows_inserted: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct RowsCopiedOutput { rows_copied: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct MergeTablesOutput { rows_inserted: Option<u64>, rows_skipped: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct WhereBuilderOutput { #[serde(rename = "where")] r#where: String, params: Vec<Value> }
+#[derive(Serialize, JsonSchema)]
+struct IncrementOutput { new_values: Option<Vec<Value>>, affected_row_count: Option<u64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct DistinctValuesOutput { values: Vec<Value>, total: i64 }
+#[derive(Serialize, JsonSchema)]
+struct ValueCount { value: Value, count: i64 }
+#[derive(Serialize, JsonSchema)]
+struct ValueCountsOutput { value_counts: Vec<ValueCount> }
+#[derive(Serialize, JsonSchema)]
+struct RowDifference { column: String, value_a: Value, value_b: Value }
+#[derive(Serialize, JsonSchema)]
+struct RowDiffOutput { differences: Vec<RowDifference>, matching_columns: usize }
+#[derive(Serialize, JsonSchema)]
+struct MatchCountOutput { valid: bool, matches: Option<i64>, error: Option<String> }
+#[derive(Serialize, JsonSchema)]
+struct HasColumnOutput { exists: bool }
+#[derive(Serialize, JsonSchema)]
+struct EnvOutput { env: Value, error: Option<String>, raw: Option<String> }
+#[derive(Serialize, JsonSchema)]
+struct NotebookIdOutput { id: Option<i64>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct NotebookMergeOutput { target_length: Option<i64>, source_deleted: Option<bool>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct NotebookItem { id: i64, title: String, snippet: String, char_count: i64, data: Option<String> }
+#[derive(Serialize, JsonSchema)]
+struct NotebookItemsOutput { items: Vec<NotebookItem> }
+#[derive(Serialize, JsonSchema)]
+struct NotebookRecentItem { id: i64, title: String, snippet: String, updated_at: Value }
+#[derive(Serialize, JsonSchema)]
+struct NotebookRecentOutput { items: Vec<NotebookRecentItem> }
+// Empty object when the notebook doesn't exist
+#[derive(Serialize, JsonSchema)]
+struct NotebookGetOutput {
+    id: Option<i64>,
+    title: Option<String>,
+    // Holds the body, or the base64 payload when compressed
+    data: Option<String>,
+    char_count: Option<i64>,
+    word_count: Option<u64>,
+    encoding: Option<String>,
+    #[serde(rename = "_compressed")] compressed: Option<bool>,
+}
+#[derive(Serialize, JsonSchema)]
+struct TagsOutput { tags: Vec<String> }
+#[derive(Serialize, JsonSchema)]
+struct TagCount { tag: String, notebook_count: i64 }
+#[derive(Serialize, JsonSchema)]
+struct TagCountsOutput { tags: Vec<TagCount> }
+#[derive(Serialize, JsonSchema)]
+struct OkOutput { ok: Option<bool>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct ReindexOutput { ok: Option<bool>, elapsed_ms: Option<u64>, analyzed: Option<bool>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct HistoryTableOutput { history_table: Option<String>, triggers_created: Option<usize>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct CreateTableOutput { ddl: Option<String>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct ExportEncryptedOutput { ok: bool, destination_path: String }
+#[derive(Serialize, JsonSchema)]
+struct UserVersionOutput { version: i32 }
+#[derive(Serialize, JsonSchema)]
+struct SetUserVersionOutput { version: Option<i32>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct ApplicationIdOutput { application_id: i32 }
+#[derive(Serialize, JsonSchema)]
+struct SetApplicationIdOutput { application_id: Option<i32>, #[serde(flatten)] dry_run: DryRunFields }
+#[derive(Serialize, JsonSchema)]
+struct SqlitePingOutput { ok: bool, latency_ms: u64, latency_us: u64, pool_idle: u32 }
+#[derive(Serialize, JsonSchema)]
+struct ChecksumOutput { checksum: String, row_count: u64, truncated: bool }
+#[derive(Serialize, JsonSchema)]
+struct ForeignKey { from_column: String, to_table: String, to_column: Option<String>, on_update: String, on_delete: String }
+#[derive(Serialize, JsonSchema)]
+struct ForeignKeysOutput { foreign_keys: Vec<ForeignKey> }
+#[derive(Serialize, JsonSchema)]
+struct CancelOutput { cancelled: bool, query_id: Option<String> }
+#[derive(Serialize, JsonSchema)]
+struct ForeignKeyCheckOutput { violations: Vec<serde_json::Map<String, Value>>, violation_count: u64, ok: bool }
+#[derive(Serialize, JsonSchema)]
+struct WalInfoOutput {
+    journal_mode: String,
+    wal_autocheckpoint: i64,
+    checkpoint_mode: Option<String>,
+    busy: bool,
+    wal_size_bytes: Option<u64>,
+    log_frames: i64,
+    checkpointed_frames: i64,
+    needs_checkpoint: bool,
+}
+#[derive(Serialize, JsonSchema)]
+struct SqlOutput { sql: String }
+#[derive(Serialize, JsonSchema)]
+struct DumpDataOutput { sql: String, rows_dumped: u64, truncated: bool }
+#[derive(Serialize, JsonSchema)]
+struct DbSizeOutput { db_size_bytes: Option<u64>, wal_size_bytes: Option<u64>, shm_size_bytes: Option<u64>, total_size_bytes: u64 }
+#[derive(Serialize, JsonSchema)]
+struct VersionInfoOutput { version: String, source_id: String, compile_options: Vec<String> }
+#[derive(Serialize, JsonSchema)]
+struct PragmasOutput {
+    journal_mode: String,
+    synchronous: i64,
+    cache_size: i64,
+    mmap_size: i64,
+    busy_timeout: i64,
+    foreign_keys: i64,
+    temp_store: i64,
+    wal_autocheckpoint: i64,
+    page_size: i64,
+}
+#[derive(Serialize, JsonSchema)]
+struct DatabaseEntry { seq: i64, name: String, file: String }
+#[derive(Serialize, JsonSchema)]
+struct DatabasesOutput { databases: Vec<DatabaseEntry> }
+#[derive(Serialize, JsonSchema)]
+struct ViewsOutput { views: Vec<String> }
+#[derive(Serialize, JsonSchema)]
+struct ViewDefinitionOutput { name: String, sql: String }
+#[derive(Serialize, JsonSchema)]
+struct TriggerEntry { name: String, table: String, sql: Option<String> }
+#[derive(Serialize, JsonSchema)]
+struct TriggersOutput { triggers: Vec<TriggerEntry> }
+#[derive(Serialize, JsonSchema)]
+struct ToolStatsEntry { call_count: u64, total_us: u64, avg_us: u64, error_count: u64 }
+#[derive(Serialize, JsonSchema)]
+struct QueryStatsOutput { stats: HashMap<String, ToolStatsEntry> }
+#[derive(Serialize, JsonSchema)]
+struct BusyStatsEntry { busy_count: u64, last_busy_ms: u64 }
+#[derive(Serialize, JsonSchema)]
+struct LockStatsOutput { stats: HashMap<String, BusyStatsEntry> }
+
+#[derive(Deserialize, Default)]
+struct FileConfig {
+    database: Option<DatabaseConfig>,
+    // [tool_limits] sqlite_select.max_rows = 1000
+    #[serde(default)]
+    tool_limits: HashMap<String, HashMap<String, usize>>,
+    // [rate_limits] sqlite_select = { rps = 5.0, burst = 10 }
+    #[serde(default)]
+    rate_limits: HashMap<String, RateLimitConfig>,
+    // Top-level required_tables = ["notebooks"]; startup fails if any is missing
+    #[serde(default)]
+    required_tables: Vec<String>,
+    // [required_columns] notebooks = ["id", "title"]
+    #[serde(default)]
+    required_columns: HashMap<String, Vec<String>>,
+}
+#[derive(Deserialize)]
+struct DatabaseConfig { url: String }
+
+fn load_file_config() -> Result<FileConfig> {
+    // Try ./config.toml and alongside the executable; a file that exists but doesn't parse is fatal
+    let candidates = [
+        std::env::current_dir().ok().map(|p| p.join("config.toml")),
+        std::env::current_exe().ok().and_then(|p| p.parent().map(|d| d.join("config.toml"))),
+    ];
+    for path in candidates.into_iter().flatten() {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            return toml::from_str::<FileConfig>(&text)
+                .map_err(|e| anyhow::anyhow!("refusing to start: {} is not valid config: {}", path.display(), e));
+        }
+    }
+    Ok(FileConfig::default())
+}
+
+fn load_db_url(cfg: &FileConfig) -> String {
+    if let Ok(v) = std::env::var("DATABASE_URL") { return v; }
+    cfg.database.as_ref().map(|d| d.url.clone()).unwrap_or_else(|| "sqlite://./app.sqlite".to_string())
+}
+
+// Comma-separated env list; None when unset or empty
+fn env_list(name: &str) -> Option<Vec<String>> {
+    let v = std::env::var(name).ok()?;
+    let items = v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    if items.is_empty() { None } else { Some(items) }
+}
+
+// MASKED_COLUMNS (JSON table -> [columns]) merged with WARP_SQLITE_REDACT_COLUMNS (table.column list), all lowercased
+fn load_masked_columns() -> Result<HashMap<String, Vec<String>>> {
+    let configured: HashMap<String, Vec<String>> = match std::env::var("MASKED_COLUMNS") {
+        Ok(raw) => serde_json::from_str(&raw).map_err(|e| anyhow::anyhow!("MASKED_COLUMNS must be a JSON object of table -> [columns]: {}", e))?,
+        Err(_) => HashMap::new(),
+    };
+    let mut masked: HashMap<String, Vec<String>> = HashMap::new();
+    for (table, columns) in configured {
+        masked.entry(table.to_ascii_lowercase()).or_default().extend(columns.iter().map(|c| c.to_ascii_lowercase()));
+    }
+    for entry in env_list("WARP_SQLITE_REDACT_COLUMNS").unwrap_or_default() {
+        let (table, column) = entry.split_once('.')
+            .ok_or_else(|| anyhow::anyhow!("WARP_SQLITE_REDACT_COLUMNS entries must be table.column, got {}", entry))?;
+        masked.entry(table.to_ascii_lowercase()).or_default().push(column.to_ascii_lowercase());
+    }
+    Ok(masked)
+}
+
+// config.toml [tool_limits]: one of max_rows / max_items per tool; other keys are an error
+fn load_tool_limits(cfg: &FileConfig) -> Result<HashMap<String, usize>> {
+    let registered = SqliteService::tool_router().list_all().into_iter().map(|t| t.name.to_string()).collect::<Vec<_>>();
+    let mut limits = HashMap::new();
+    for (tool, entry) in &cfg.tool_limits {
+        if !registered.contains(tool) { eprintln!("warning: unknown tool name in [tool_limits]: {}", tool); }
+        if let Some(key) = entry.keys().find(|k| !TOOL_LIMIT_KEYS.contains(&k.as_str())) {
+            anyhow::bail!("[tool_limits] {}.{} is not a known limit (expected max_rows or max_items)", tool, key);
+        }
+        let mut values = TOOL_LIMIT_KEYS.iter().filter_map(|k| entry.get(*k));
+        match (values.next(), values.next()) {
+            (Some(n), None) => { limits.insert(tool.clone(), *n); }
+            (Some(_), Some(_)) => anyhow::bail!("[tool_limits] {} sets both max_rows and max_items; keep one", tool),
+            _ => {}
+        }
+    }
+    Ok(limits)
+}
+
+// Applies WARP_SQLITE_ENABLED_TOOLS / WARP_SQLITE_DISABLED_TOOLS; disabled subtracts from enabled
+fn filter_tools(router: &mut ToolRouter<SqliteService>) {
+    let registered = router.list_all().into_iter().map(|t| t.name.to_string()).collect::<Vec<_>>();
+    let enabled = env_list("WARP_SQLITE_ENABLED_TOOLS");
+    let disabled = env_list("WARP_SQLITE_DISABLED_TOOLS").unwrap_or_default();
+    for name in enabled.iter().flatten().chain(disabled.iter()) {
+        if !registered.contains(name) { eprintln!("warning: unknown tool name in tool filter: {}", name); }
+    }
+    for name in &registered {
+        let allowed = enabled.as_ref().map(|e| e.contains(name)).unwrap_or(true);
+        if !allowed || disabled.contains(name) { router.remove_route(name); }
+    }
+}
+
+// Declares each tool's result shape as its outputSchema
+fn set_output_schemas(router: &mut ToolRouter<SqliteService>) {
+    use rmcp::handler::server::tool::schema_for_type;
+    let schemas = [
+        ("sqlite_insert", schema_for_type::<InsertOutput>()),
+        ("sqlite_insert_default_row", schema_for_type::<InsertOutput>()),
+        ("sqlite_upsert_or_fail", schema_for_type::<InsertOutput>()),
+        ("sqlite_select", schema_for_type::<SelectOutput>()),
+        ("sqlite_multi_select", schema_for_type::<MultiSelectOutput>()),
+        ("sqlite_select_in", schema_for_type::<RowsOutput>()),
+        ("sqlite_select_between", schema_for_type::<RowsOutput>()),
+        ("sqlite_select_like", schema_for_type::<RowsOutput>()),
+        ("sqlite_select_null", schema_for_type::<NullRowsOutput>()),
+        ("sqlite_estimate", schema_for_type::<EstimateOutput>()),
+        ("sqlite_select_not_null", schema_for_type::<NullRowsOutput>()),
+        ("sqlite_update", schema_for_type::<AffectedRowCountOutput>()),
+        ("sqlite_compare_and_set", schema_for_type::<CompareAndSetOutput>()),
+        ("sqlite_delete", schema_for_type::<DeleteOutput>()),
+        ("sqlite_distinct_values", schema_for_type::<DistinctValuesOutput>()),
+        ("sqlite_value_counts", schema_for_type::<ValueCountsOutput>()),
+        ("sqlite_sample", schema_for_type::<RowsOutput>()),
+        ("sqlite_row_diff", schema_for_type::<RowDiffOutput>()),
+        ("sqlite_truncate", schema_for_type::<RowsDeletedOutput>()),
+        ("sqlite_match_count", schema_for_type::<MatchCountOutput>()),
+        ("sqlite_has_column", schema_for_type::<HasColumnOutput>()),
+        ("sqlite_where_builder", schema_for_type::<WhereBuilderOutput>()),
+        ("sqlite_increment", schema_for_type::<IncrementOutput>()),
+        ("sqlite_insert_from_select", schema_for_type::<RowsInsertedOutput>()),
+        ("sqlite_copy_rows", schema_for_type::<RowsCopiedOutput>()),
+        ("sqlite_merge_tables", schema_for_type::<MergeTablesOutput>()),
+        ("sqlite_swap_tables", schema_for_type::<OkOutput>()),
+        ("sqlite_delete_batch", schema_for_type::<BatchDeleteOutput>()),
+        ("sqlite_update_where_in", schema_for_type::<UpdateWhereInOutput>()),
+        ("mcp_register_server", schema_for_type::<RowsAffectedOutput>()),
+        ("mcp_unregister_server", schema_for_type::<RowsAffectedOutput>()),
+        ("mcp_set_env", schema_for_type::<RowsAffectedOutput>()),
+        ("mcp_get_env", schema_for_type::<EnvOutput>()),
+        ("notebook_create", schema_for_type::<NotebookIdOutput>()),
+        ("notebook_append", schema_for_type::<RowsAffectedOutput>()),
+        ("notebook_delete", schema_for_type::<RowsAffectedOutput>()),
+        ("notebook_bulk_delete", schema_for_type::<NotebookBulkDeleteOutput>()),
+        ("notebook_merge", schema_for_type::<NotebookMergeOutput>()),
+        ("notebook_list", schema_for_type::<NotebookItemsOutput>()),
+        ("notebook_recent", schema_for_type::<NotebookRecentOutput>()),
+        ("notebook_word_search", schema_for_type::<NotebookItemsOutput>()),
+        ("notebook_list_by_ids", schema_for_type::<NotebookItemsOutput>()),
+        ("notebook_get", schema_for_type::<NotebookGetOutput>()),
+        ("notebook_tag_add", schema_for_type::<RowsAffectedOutput>()),
+        ("notebook_tag_remove", schema_for_type::<RowsAffectedOutput>()),
+        ("notebook_get_tags", schema_for_type::<TagsOutput>()),
+        ("notebook_list_all_tags", schema_for_type::<TagCountsOutput>()),
+        ("notebook_list_by_tag", schema_for_type::<NotebookItemsOutput>()),
+        ("sqlite_rebuild_fts", schema_for_type::<OkOutput>()),
+        ("sqlite_reindex", schema_for_type::<ReindexOutput>()),
+        ("sqlite_history_table", schema_for_type::<HistoryTableOutput>()),
+        ("sqlite_create_table", schema_for_type::<CreateTableOutput>()),
+        ("sqlite_drop_table", schema_for_type::<OkOutput>()),
+        ("sqlite_export_encrypted", schema_for_type::<ExportEncryptedOutput>()),
+        ("sqlite_set_user_version", schema_for_type::<SetUserVersionOutput>()),
+        ("sqlite_get_user_version", schema_for_type::<UserVersionOutput>()),
+        ("sqlite_set_application_id", schema_for_type::<SetApplicationIdOutput>()),
+        ("sqlite_get_application_id", schema_for_type::<ApplicationIdOutput>()),
+        ("sqlite_ping", schema_for_type::<SqlitePingOutput>()),
+        ("ping", schema_for_type::<SqlitePingOutput>()),
+        ("sqlite_cancel", schema_for_type::<CancelOutput>()),
+        ("sqlite_compute_checksum", schema_for_type::<ChecksumOutput>()),
+        ("sqlite_foreign_keys", schema_for_type::<ForeignKeysOutput>()),
+        ("sqlite_foreign_key_check", schema_for_type::<ForeignKeyCheckOutput>()),
+        ("sqlite_wal_info", schema_for_type::<WalInfoOutput>()),
+        ("sqlite_dump_schema", schema_for_type::<SqlOutput>()),
+        ("sqlite_dump_data", schema_for_type::<DumpDataOutput>()),
+        ("sqlite_db_size", schema_for_type::<DbSizeOutput>()),
+        ("sqlite_version_info", schema_for_type::<VersionInfoOutput>()),
+        ("sqlite_pragmas", schema_for_type::<PragmasOutput>()),
+        ("sqlite_list_databases", schema_for_type::<DatabasesOutput>()),
+        ("sqlite_list_views", schema_for_type::<ViewsOutput>()),
+        ("sqlite_get_view_definition", schema_for_type::<ViewDefinitionOutput>()),
+        ("sqlite_list_triggers", schema_for_type::<TriggersOutput>()),
+        ("sqlite_query_stats", schema_for_type::<QueryStatsOutput>()),
+        ("sqlite_lock_stats", schema_for_type::<LockStatsOutput>()),
+    ];
+    for (name, schema) in schemas {
+        if let Some(route) = router.map.get_mut(name) { route.attr.output_schema = Some(Arc::new(schema)); }
+    }
+}
+
+// Compares PRAGMA application_id with WARP_SQLITE_EXPECTED_APPLICATION_ID (decimal or 0x hex);
+// a mismatch is fatal with WARP_SQLITE_STRICT_APP_ID=1, otherwise a warning
+async fn check_application_id(pool: &Pool<Sqlite>) -> Result<()> {
+    let Ok(raw) = std::env::var("WARP_SQLITE_EXPECTED_APPLICATION_ID") else { return Ok(()) };
+    let expected = match raw.strip_prefix("0x").or_else(|| raw.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).map(|v| v as i32).ok(),
+        None => raw.parse::<i32>().ok(),
+    }.ok_or_else(|| anyhow::anyhow!("WARP_SQLITE_EXPECTED_APPLICATION_ID is not an integer: {}", raw))?;
+    let actual: i32 = sqlx::query_scalar("PRAGMA application_id").fetch_one(pool).await?;
+    if actual != expected {
+        let msg = format!("database application_id is {} but WARP_SQLITE_EXPECTED_APPLICATION_ID is {}", actual, expected);
+        let strict = std::env::var("WARP_SQLITE_STRICT_APP_ID").map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false);
+        if strict { anyhow::bail!("refusing to start: {}", msg); }
+        eprintln!("warning: {}", msg);
+    }
+    Ok(())
+}
+
+// Tables and columns the notebook and MCP tools rely on
+const WARP_TABLES: &[(&str, &[&str])] = &[
+    ("notebooks", &["id", "title", "data"]),
+    ("active_mcp_servers", &["mcp_server_uuid"]),
+    ("mcp_environment_variables", &["mcp_server_uuid", "environment_variables"]),
+];
+
+// Warns about missing Warp tables/columns; fatal with WARP_SQLITE_STRICT_SCHEMA=1
+async fn check_warp_schema(pool: &Pool<Sqlite>) -> Result<()> {
+    let mut problems = Vec::new();
+    for (table, expected) in WARP_TABLES {
+        let actual: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?1) ORDER BY cid")
+            .bind(table)
+            .fetch_all(pool).await?;
+        if actual.is_empty() { problems.push(format!("table {} is missing", table)); continue; }
+        let missing = expected.iter().filter(|c| !actual.iter().any(|a| a == *c)).collect::<Vec<_>>();
+        if !missing.is_empty() {
+            problems.push(format!("table {} lacks {:?}: expected columns {:?}, found {:?}", table, missing, expected, actual));
+        }
+    }
+    if problems.is_empty() { return Ok(()); }
+    let msg = format!("database doesn't match the Warp schema the notebook/MCP tools expect: {}", problems.join("; "));
+    let strict = std::env::var("WARP_SQLITE_STRICT_SCHEMA").map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false);
+    if strict { anyhow::bail!("refusing to start: {}", msg); }
+    eprintln!("warning: {}", msg);
+    Ok(())
+}
+
+// config.toml required_tables / required_columns; any gap is fatal
+async fn check_required_schema(pool: &Pool<Sqlite>, cfg: &FileConfig) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut tables = cfg.required_tables.iter().collect::<Vec<_>>();
+    tables.extend(cfg.required_columns.keys().filter(|t| !cfg.required_tables.contains(t)));
+    for table in tables {
+        let exists: Option<i64> = sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE type IN ('table', 'view') AND name = ?1")
+            .bind(table)
+            .fetch_optional(pool).await?;
+        if exists.is_none() { problems.push(format!("table {} is missing", table)); continue; }
+        let Some(required) = cfg.required_columns.get(table) else { continue };
+        let actual: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?1)")
+            .bind(table)
+            .fetch_all(pool).await?;
+        let missing = required.iter().filter(|c| !actual.contains(c)).collect::<Vec<_>>();
+        if !missing.is_empty() { problems.push(format!("table {} lacks columns {:?}", table, missing)); }
+    }
+    if !problems.is_empty() {
+        anyhow::bail!("refusing to start: database doesn't match config.toml required_tables/required_columns: {}", problems.join("; "));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // DATABASE_URL example: sqlite:///Users/samuelatagana/Library/Application Support/dev.warp.Warp-Stable/warp.sqlite
+    let cfg = load_file_config()?;
+    let db_url = load_db_url(&cfg);
+
+    // wal_autocheckpoint is per-connection, so apply it as each pooled connection opens
+    let wal_autocheckpoint = std::env::var("WARP_SQLITE_WAL_AUTOCHECKPOINT").ok().and_then(|v| v.parse::<i64>().ok());
+    let pool = SqlitePoolOptions::new()
+        .max_connections(MAX_CONNECTIONS)
+        .after_connect(move |conn, _meta| Box::pin(async move {
+            if let Some(pages) = wal_autocheckpoint {
+                sqlx::query(&format!("PRAGMA wal_autocheckpoint = {}", pages)).execute(conn).await?;
+            }
+            Ok(())
+        }))
+        .connect(&db_url)
+        .await?;
+    // Best-effort WAL
+    let _ = sqlx::query("PRAGMA journal_mode = WAL;").execute(&pool).await;
+
+    check_application_id(&pool).await?;
+    check_warp_schema(&pool).await?;
+    check_required_schema(&pool, &cfg).await?;
+    ensure_idempotency_table(&pool).await;
+    spawn_idempotency_pruner(pool.clone());
+    let jsonb_supported = sqlx::query("SELECT jsonb('{}')").execute(&pool).await.is_ok();
+
+    let state = Arc::new(AppState {
+        pool,
+        jsonb_supported,
+        compress_threshold: std::env::var("WARP_SQLITE_COMPRESS_THRESHOLD").or_else(|_| std::env::var("COMPRESS_THRESHOLD_BYTES")).ok()
+            .and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_COMPRESS_THRESHOLD),
+        max_params: std::env::var("WARP_SQLITE_MAX_PARAMS").ok().and_then(|v| v.parse().ok()).filter(|n| *n > 0).unwrap_or(DEFAULT_MAX_PARAMS),
+        ident_re: Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap(),
+        // ALLOWED_TABLES="*" (or unset) allows everything
+        allowed_tables: env_list("ALLOWED_TABLES")
+            .filter(|t| !t.iter().any(|x| x == "*"))
+            .map(|t| t.into_iter().map(|x| x.to_ascii_lowercase()).collect()),
+        denied_tables: DEFAULT_DENIED_TABLES.iter().map(|t| t.to_string())
+            .chain(env_list("DENIED_TABLES").unwrap_or_default().into_iter().map(|t| t.to_ascii_lowercase()))
+            .collect(),
+        request_cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(IDEMPOTENCY_CACHE_SIZE).unwrap()))),
+        max_result_rows: std::env::var("MAX_RESULT_ROWS").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_MAX_RESULT_ROWS),
+        tool_limits: load_tool_limits(&cfg)?,
+        tool_stats: Arc::new(Mutex::new(HashMap::new())),
+        busy_stats: Arc::new(Mutex::new(HashMap::new())),
+        rate_limits: cfg.rate_limits.clone(),
+        rate_buckets: Arc::new(Mutex::new(HashMap::new())),
+        // One connection short of the pool by default, leaving room for the tools that skip the semaphore
+        query_permits: Arc::new(tokio::sync::Semaphore::new(
+            std::env::var("MAX_CONCURRENT_QUERIES").ok().and_then(|v| v.parse().ok()).filter(|n| *n > 0)
+                .unwrap_or(MAX_CONNECTIONS as usize - 1),
+        )),
+        read_only: std::env::var("READ_ONLY").map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false),
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        semaphore_timeout: Duration::from_millis(
+            std::env::var("SEMAPHORE_TIMEOUT_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_SEMAPHORE_TIMEOUT_MS),
+        ),
+        // Fail closed: a malformed mask must not silently expose the columns it was meant to hide
+        masked_columns: load_masked_columns()?,
+        active_queries: Arc::new(Mutex::new(Vec::new())),
+        stable_order: std::env::var("WARP_SQLITE_STABLE_ORDER").map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false),
+    });
+
+    let mut tool_router = SqliteService::tool_router();
+    set_output_schemas(&mut tool_router);
+    filter_tools(&mut tool_router);
+    let service = SqliteService { state: state.clone(), tool_router };
+    let server = service.serve(stdio()).await?;
+    let cancel = server.cancellation_token();
+    let shutdown_state = state.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        if !drain_in_flight(&shutdown_state, SHUTDOWN_DRAIN_TIMEOUT).await {
+            eprintln!("warning: {} tool call(s) still running after {:?}; shutting down anyway",
+                shutdown_state.in_flight.load(Ordering::SeqCst), SHUTDOWN_DRAIN_TIMEOUT);
+        }
+        cancel.cancel();
+    });
+    server.waiting().await?;
+    // Only checkpoint and close once nothing is mid-query; otherwise close() would wait on the stuck calls
+    if state.in_flight.load(Ordering::SeqCst) == 0 {
+        let _ = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(&state.pool).await;
+        state.pool.close().await;
+    }
+    Ok(())
+}
+
+// Refuses new tool calls, then waits up to timeout for the running ones; false when some are still running
+async fn drain_in_flight(state: &AppState, timeout: Duration) -> bool {
+    state.shutting_down.store(true, Ordering::SeqCst);
+    tokio::time::timeout(timeout, async {
+        while state.in_flight.load(Ordering::SeqCst) > 0 { tokio::time::sleep(Duration::from_millis(50)).await; }
+    }).await.is_ok()
+}
+
+// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut term = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(term) => term,
+            Err(_) => { let _ = tokio::signal::ctrl_c().await; return; }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    { let _ = tokio::signal::ctrl_c().await; }
+}
+
+#[derive(Clone)]
+struct SqliteService {
+    state: Arc<AppState>,
+    tool_router: ToolRouter<SqliteService>,
+}
+
+#[tool_router]
+impl SqliteService {
+    #[tool(description = "Insert a row; returns last_insert_rowid. date_columns are normalized to YYYY-MM-DD HH:MM:SS; \
+                          string values in json_validate_columns must be valid JSON; \
+                          jsonb_columns are stored as JSONB via jsonb(?) (SQLite 3.45+)")]
+    pub async fn sqlite_insert(&self, params: Parameters<InsertInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let mut input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_insert:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        normalize_dates(state, input.date_columns.as_deref(), &mut input.values).await?;
+        validate_json_columns(input.json_validate_columns.as_deref(), &input.values)?;
+        let jsonb_cols = jsonb_columns(state, input.jsonb_columns.as_deref())?;
+        let mut cols = Vec::new();
+        let mut binds = Vec::new();
+        for (k, v) in input.values.iter() {
+            if !is_valid_ident(&state.ident_re, k) {
+                return Err(ErrorData::invalid_params(format!("Invalid column: {}", k), None));
+            }
+            cols.push(k.clone());
+            binds.push(v.clone());
+        }
+        if cols.is_empty() {
+            return Err(ErrorData::invalid_params("No columns provided".to_string(), None));
+        }
+        let placeholders = cols.iter().map(|c| if jsonb_cols.contains(c) { "jsonb(?)" } else { "?" }).collect::<Vec<_>>().join(", ");
+        let sql = format!("INSERT INTO {} ({}) VALUES ({})", input.table, cols.join(", "), placeholders);
+        let param_count = binds.len();
+        state.check_params(param_count)?;
+        if input.dry_run { return dry_run_result(&sql, param_count); }
+        let mut q = sqlx::query(&sql);
+        for v in binds { q = bind_value(q, v).map_err(|e| ErrorData::internal_error(e.to_string(), None))?; }
+        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "last_insert_rowid": res.last_insert_rowid() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Insert a row, failing with \"Row already exists\" (data.kind \"conflict\") instead of ignoring it when \
+                          conflict_columns clash with an existing row; returns last_insert_rowid")]
+    pub async fn sqlite_upsert_or_fail(&self, params: Parameters<UpsertOrFailInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_upsert_or_fail:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if input.values.is_empty() { return Err(ErrorData::invalid_params("No columns provided".to_string(), None)); }
+        if input.conflict_columns.is_empty() { return Err(ErrorData::invalid_params("No conflict_columns provided".to_string(), None)); }
+        for c in input.values.keys().chain(input.conflict_columns.iter()) {
+            if !is_valid_ident(&state.ident_re, c) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); }
+        }
+        let cols = input.values.keys().cloned().collect::<Vec<_>>();
+        let sql = format!("INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) DO NOTHING",
+            input.table, cols.join(", "), vec!["?"; cols.len()].join(", "), input.conflict_columns.join(", "));
+        state.check_params(cols.len())?;
+        if input.dry_run { return dry_run_result(&sql, cols.len()); }
+        let mut q = sqlx::query(&sql);
+        for (_, v) in input.values { q = bind_value(q, v).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        if res.rows_affected() == 0 {
+            return Err(ErrorData::invalid_params(
+                "Row already exists".to_string(),
+                Some(serde_json::json!({ "kind": "conflict", "table": input.table, "conflict_columns": input.conflict_columns })),
+            ));
+        }
+        let content = Content::json(serde_json::json!({ "last_insert_rowid": res.last_insert_rowid() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Insert a row using only column defaults (INSERT ... DEFAULT VALUES); fails if a NOT NULL column has no default; \
+                          returns last_insert_rowid")]
+    pub async fn sqlite_insert_default_row(&self, params: Parameters<InsertDefaultRowInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        let sql = format!("INSERT INTO {} DEFAULT VALUES", input.table);
+        if input.dry_run { return dry_run_result(&sql, 0); }
+        let res = sqlx::query(&sql).execute(&state.pool).await.map_err(|e| match e {
+            // Usually a NOT NULL column without a default; pass SQLite's message through
+            sqlx::Error::Database(db) => ErrorData::invalid_params(format!("Cannot insert a default row into {}: {}", input.table, db.message()), None),
+            e => ErrorData::internal_error(e.to_string(), None),
+        })?;
+        let content = Content::json(serde_json::json!({ "last_insert_rowid": res.last_insert_rowid() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Select rows; returns { rows, row_count, truncated, limit_applied }. date_columns are returned as ISO-8601 via datetime(); \
+                          decimal_columns are returned as strings to preserve precision; \
+                          null_handling \"omit\" drops null cells from rows; \
+                          joins ([{ type: inner|left, table, on_left, on_right }]) require columns as table.column, which also key the rows; \
+                          chunked returns one page of chunk_rows rows with next_cursor (null on the last page) and total_row_count so far; \
+                          pass next_cursor back as cursor for the next page. Pages follow order_by, else the rowid or primary key")]
+    pub async fn sqlite_select(&self, params: Parameters<SelectInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let max_rows = self.state.row_limit("sqlite_select", usize::MAX);
+        if params.0.chunked.unwrap_or(false) { return self.select_page(params.0, max_rows).await; }
+        let compress = params.0.compress.unwrap_or(false);
+        let out = self.run_select(params.0, max_rows).await?;
+        json_result(&self.state, out.into_json(), compress)
+    }
+
+    #[tool(description = "Select rows whose column is in values, without writing the IN clause; values are bound and \
+                          chunked under the bind parameter limit, with limit/offset applied across all chunks; returns { rows, row_count, truncated, limit_applied }")]
+    pub async fn sqlite_select_in(&self, params: Parameters<SelectInInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        // Checked up front so an empty values list can't skip the access check
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        // limit/offset count rows across chunks, so each chunk needs a fixed order for a retry to see the same rows
+        if (input.limit.is_some() || input.offset.is_some()) && stable_order_by(&state.pool, &input.table).await?.is_none() {
+            return Err(ErrorData::invalid_params("limit and offset need a table with a rowid or primary key".to_string(), None));
+        }
+        let max_rows = state.row_limit("sqlite_select_in", usize::MAX);
+        let offset = input.offset.unwrap_or(0).max(0) as usize;
+        let limit = input.limit.map_or(max_rows, |l| (l.max(0) as usize).min(max_rows));
+        // Rows read across all chunks, offset included, never exceed the row cap
+        let wanted = offset.saturating_add(limit).min(max_rows);
+        let mut rows = Vec::new();
+        let mut truncated = false;
+        for chunk in input.values.chunks(state.max_params) {
+            // Once wanted is reached, later chunks run with a cap of 0, which only probes for one more match
+            let select = SelectInput {
+                table: input.table.clone(),
+                columns: input.columns.clone(),
+                r#where: Some(format!("{} IN ({})", input.column, vec!["?"; chunk.len()].join(", "))),
+                params: Some(chunk.to_vec()),
+                stable: Some(true),
+                ..Default::default()
+            };
+            let out = self.run_select(select, wanted - rows.len()).await?;
+            truncated |= out.truncated;
+            rows.extend(out.rows);
+            if truncated { break; }
+        }
+        let rows = rows.into_iter().skip(offset).take(limit).collect::<Vec<_>>();
+        let limit_applied = (limit != usize::MAX).then(|| limit.min(i64::MAX as usize) as i64);
+        let content = Content::json(SelectedRows { rows, truncated, limit_applied }.into_json())
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Select rows whose column is BETWEEN low AND high (inclusive), with low and high bound as parameters; returns { rows, row_count, truncated, limit_applied }")]
+    pub async fn sqlite_select_between(&self, params: Parameters<SelectBetweenInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        if !is_valid_ident(&self.state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        let select = SelectInput {
+            table: input.table,
+            columns: input.columns,
+            r#where: Some(format!("{} BETWEEN ? AND ?", input.column)),
+            params: Some(vec![input.low, input.high]),
+            order_by: input.order_by,
+            limit: input.limit,
+            ..Default::default()
+        };
+        let out = self.run_select(select, self.state.row_limit("sqlite_select_between", usize::MAX)).await?;
+        let content = Content::json(out.into_json())
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Select rows whose column matches a LIKE pattern (% and _ wildcards), bound as a parameter. \
+                          Case-insensitive by default; returns { rows, row_count, truncated, limit_applied }")]
+    pub async fn sqlite_select_like(&self, params: Parameters<SelectLikeInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        if !is_valid_ident(&self.state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        let (cond, pattern) = if input.case_sensitive {
+            // SQLite's LIKE ignores ASCII case, so match with GLOB using the equivalent pattern
+            let mut glob = String::with_capacity(input.pattern.len());
+            for ch in input.pattern.chars() {
+                match ch {
+                    '%' => glob.push('*'),
+                    '_' => glob.push('?'),
+                    '*' | '?' | '[' => { glob.push('['); glob.push(ch); glob.push(']'); }
+                    _ => glob.push(ch),
+                }
+            }
+            (format!("{} GLOB ?", input.column), glob)
+        } else {
+            (format!("lower({}) LIKE lower(?)", input.column), input.pattern)
+        };
+        let select = SelectInput {
+            table: input.table,
+            columns: input.columns,
+            r#where: Some(cond),
+            params: Some(vec![Value::String(pattern)]),
+            limit: input.limit,
+            ..Default::default()
+        };
+        let out = self.run_select(select, self.state.row_limit("sqlite_select_like", usize::MAX)).await?;
+        let content = Content::json(out.into_json())
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Select rows where column IS NULL (or IS NOT NULL with is_null: false); \
+                          returns { rows, row_count, truncated, limit_applied, total_count } with total_count ignoring the limit")]
+    pub async fn sqlite_select_null(&self, params: Parameters<SelectNullInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let is_null = params.0.is_null.unwrap_or(true);
+        self.select_null(params.0, is_null, "sqlite_select_null").await
+    }
+
+    #[tool(description = "Select rows where column IS NOT NULL; returns { rows, row_count, truncated, limit_applied, total_count } \
+                          with total_count ignoring the limit")]
+    pub async fn sqlite_select_not_null(&self, params: Parameters<SelectNullInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        self.select_null(params.0, false, "sqlite_select_not_null").await
+    }
+
+    #[tool(description = "Estimate the cost of a select (same fields as sqlite_select) from EXPLAIN QUERY PLAN without running it. \
+                          Returns { uses_index, full_scans, steps } where each step has detail, access (search, scan or other), \
+                          index and estimated_rows when SQLite reports them")]
+    pub async fn sqlite_estimate(&self, params: Parameters<SelectInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (sql, binds, _) = self.select_sql(&input, state.row_limit("sqlite_select", usize::MAX)).await?;
+        let sql = format!("EXPLAIN QUERY PLAN {}", sql);
+        let mut q = sqlx::query(&sql);
+        for p in binds { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+        let rows = q.fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let index_re = Regex::new(r"USING (?:COVERING )?INDEX (\S+)|USING (INTEGER PRIMARY KEY|PRIMARY KEY)").unwrap();
+        let rows_re = Regex::new(r"~(\d+) rows").unwrap();
+        let steps = rows.iter()
+            .map(|r| {
+                let detail: String = r.try_get("detail").unwrap_or_default();
+                let access = if detail.starts_with("SEARCH") { "search" } else if detail.starts_with("SCAN") { "scan" } else { "other" };
+                let index = index_re.captures(&detail).and_then(|c| c.get(1).or(c.get(2))).map(|m| m.as_str().to_string());
+                let estimated_rows = rows_re.captures(&detail).and_then(|c| c[1].parse().ok());
+                EstimateStep { access: access.to_string(), index, estimated_rows, detail }
+            })
+            .collect::<Vec<_>>();
+        let uses_index = steps.iter().any(|s| s.index.is_some());
+        // A SCAN without an index reads every row of its table
+        let full_scans = steps.iter().filter(|s| s.access == "scan" && s.index.is_none()).count();
+        let content = Content::json(EstimateOutput { uses_index, full_scans, steps })
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Run several selects in one call; returns { results } aligned with queries, each { rows, row_count, truncated, limit_applied } or { error }. \
+                          The row cap applies to the combined output")]
+    pub async fn sqlite_multi_select(&self, params: Parameters<MultiSelectInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let mut remaining = self.state.row_limit("sqlite_multi_select", usize::MAX);
+        let mut results = Vec::with_capacity(input.queries.len());
+        for query in input.queries {
+            results.push(match self.run_select(query, remaining).await {
+                Ok(out) => {
+                    remaining -= out.rows.len();
+                    out.into_json()
+                }
+                Err(e) => serde_json::json!({ "error": e.message }),
+            });
+        }
+        let content = Content::json(serde_json::json!({ "results": results }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Update rows; returns affected_row_count. date_columns are normalized to YYYY-MM-DD HH:MM:SS; \
+                          string values in json_validate_columns must be valid JSON; \
+                          jsonb_columns are stored as JSONB via jsonb(?) (SQLite 3.45+); \
+                          with max_affected the update is rolled back if it would touch more rows")]
+    pub async fn sqlite_update(&self, params: Parameters<UpdateInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let mut input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_update:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if input.set.is_empty() { return Err(ErrorData::invalid_params("No columns provided in set".to_string(), None)); }
+        normalize_dates(state, input.date_columns.as_deref(), &mut input.set).await?;
+        validate_json_columns(input.json_validate_columns.as_deref(), &input.set)?;
+        let jsonb_cols = jsonb_columns(state, input.jsonb_columns.as_deref())?;
+        let mut frags = Vec::new();
+        let mut vals = Vec::new();
+        for (k, v) in input.set.iter() {
+            if !is_valid_ident(&state.ident_re, k) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", k), None)); }
+            frags.push(if jsonb_cols.contains(k) { format!("{} = jsonb(?)", k) } else { format!("{} = ?", k) });
+            vals.push(v.clone());
+        }
+        let mut sql = format!("UPDATE {} SET {}", input.table, frags.join(", "));
+        if let Some(w) = &input.r#where { sql.push_str(" WHERE "); sql.push_str(&strip_sql_comments(w)); }
+        let param_count = vals.len() + input.params.as_ref().map_or(0, |p| p.len());
+        state.check_params(param_count)?;
+        if input.dry_run { return dry_run_result(&sql, param_count); }
+        let mut q = sqlx::query(&sql);
+        for v in vals { q = bind_value(q, v).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
+        let res = execute_capped(&state.pool, q, input.max_affected).await?;
+        let content = Content::json(serde_json::json!({ "affected_row_count": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Update only where the expect columns still hold the given values (compare-and-set); \
+                          returns { updated, affected_row_count }, updated false meaning the precondition failed")]
+    pub async fn sqlite_compare_and_set(&self, params: Parameters<CompareAndSetInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_compare_and_set:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if input.set.is_empty() { return Err(ErrorData::invalid_params("No columns provided in set".to_string(), None)); }
+        if input.expect.is_empty() { return Err(ErrorData::invalid_params("No columns provided in expect".to_string(), None)); }
+        for k in input.set.keys().chain(input.expect.keys()) {
+            if !is_valid_ident(&state.ident_re, k) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", k), None)); }
+        }
+        let set = input.set.keys().map(|k| format!("{} = ?", k)).collect::<Vec<_>>().join(", ");
+        // IS rather than = so an expected null matches a NULL column
+        let mut conds = input.expect.keys().map(|k| format!("{} IS ?", k)).collect::<Vec<_>>();
+        if let Some(w) = &input.r#where { conds.insert(0, format!("({})", strip_sql_comments(w))); }
+        let sql = format!("UPDATE {} SET {} WHERE {}", input.table, set, conds.join(" AND "));
+        let params = input.params.unwrap_or_default();
+        let param_count = input.set.len() + params.len() + input.expect.len();
+        state.check_params(param_count)?;
+        if input.dry_run { return dry_run_result(&sql, param_count); }
+        let mut q = sqlx::query(&sql);
+        for v in input.set.into_iter().map(|(_, v)| v).chain(params).chain(input.expect.into_iter().map(|(_, v)| v)) {
+            q = bind_value(q, v).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?;
+        }
+        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "updated": res.rows_affected() > 0, "affected_row_count": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Delete rows; returns affected_row_count, plus deleted_ids with return_ids. With max_affected the delete is rolled back if it would touch more rows")]
+    pub async fn sqlite_delete(&self, params: Parameters<DeleteInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_delete:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        let mut filter = String::new();
+        if let Some(w) = &input.r#where { filter.push_str(" WHERE "); filter.push_str(&strip_sql_comments(w)); }
+        let sql = format!("DELETE FROM {}{}", input.table, filter);
+        let param_count = input.params.as_ref().map_or(0, |p| p.len());
+        state.check_params(param_count)?;
+        if input.dry_run { return dry_run_result(&sql, param_count); }
+        if input.return_ids {
+            let key = match stable_order_by(&state.pool, &input.table).await? {
+                Some(k) if !k.contains(',') => k,
+                _ => return Err(ErrorData::invalid_params("return_ids needs a rowid table or a single-column primary key".to_string(), None)),
+            };
+            let mut tx = state.pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            let ids = delete_returning(&mut tx, &input.table, &key, &filter, input.params.unwrap_or_default()).await?;
+            if let Some(max) = input.max_affected.filter(|m| ids.len() as u64 > (*m).max(0) as u64) {
+                tx.rollback().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+                return Err(ErrorData::invalid_params(
+                    format!("Operation would affect {} rows, exceeding max_affected {}; rolled back", ids.len(), max),
+                    Some(serde_json::json!({ "would_affect": ids.len(), "max_affected": max })),
+                ));
+            }
+            tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            let content = Content::json(serde_json::json!({ "affected_row_count": ids.len(), "deleted_ids": ids }))
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            let res = CallToolResult::success(vec![content]);
+            claim.complete(&res).await;
+            return Ok(res);
+        }
+        let mut q = sqlx::query(&sql);
+        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
+        let res = execute_capped(&state.pool, q, input.max_affected).await?;
+        let content = Content::json(serde_json::json!({ "affected_row_count": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Distinct values of a column, sorted, with optional where/params; returns { values, total } where total ignores the limit")]
+    pub async fn sqlite_distinct_values(&self, params: Parameters<DistinctValuesInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        if state.is_masked(&table, &input.column) { return Err(ErrorData::invalid_params(format!("Column is masked: {}", input.column), None)); }
+        let limit = input.limit.unwrap_or(100).clamp(1, state.row_limit("sqlite_distinct_values", 1000) as i64);
+        let mut distinct = format!("SELECT DISTINCT {} FROM {}", input.column, input.table);
+        if let Some(w) = &input.r#where { distinct.push_str(" WHERE "); distinct.push_str(&strip_sql_comments(w)); }
+        let sql = format!("{} ORDER BY {} LIMIT {}", distinct, input.column, limit);
+        // Counted over the subquery rather than COUNT(DISTINCT col) so a NULL value counts like it does in values
+        let count_sql = format!("SELECT COUNT(*) FROM ({})", distinct);
+        let params = input.params.unwrap_or_default();
+        state.check_params(params.len())?;
+        let mut q = sqlx::query(&sql);
+        let mut count_q = sqlx::query(&count_sql);
+        for p in params {
+            q = bind_value(q, p.clone()).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?;
+            count_q = bind_value(count_q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?;
+        }
+        let rows = q.fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let total: i64 = count_q.fetch_one(&state.pool).await.and_then(|r| r.try_get(0))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let values = rows.iter()
+            .map(|r| row_to_json(r, &[]).remove(&input.column).unwrap_or(Value::Null))
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "values": values, "total": total }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Frequency table of a column's values, by value or (order_by_count) most common first; \
+                          returns { value_counts: [{ value, count }] }")]
+    pub async fn sqlite_value_counts(&self, params: Parameters<ValueCountsInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        if state.is_masked(&table, &input.column) { return Err(ErrorData::invalid_params(format!("Column is masked: {}", input.column), None)); }
+        let limit = input.limit.unwrap_or(100).clamp(1, state.row_limit("sqlite_value_counts", 1000) as i64);
+        let order = if input.order_by_count { format!("cnt DESC, {}", input.column) } else { input.column.clone() };
+        let sql = format!("SELECT {c} AS value, COUNT(*) AS cnt FROM {t} GROUP BY {c} ORDER BY {o} LIMIT {l}",
+            c = input.column, t = input.table, o = order, l = limit);
+        let rows = match sqlx::query(&sql).fetch_all(&state.pool).await {
+            Ok(rows) => rows,
+            Err(e) => return Err(query_error(state, &input.table, e).await),
+        };
+        let value_counts = rows.iter()
+            .map(|r| {
+                let mut obj = row_to_json(r, &[]);
+                serde_json::json!({ "value": obj.remove("value").unwrap_or(Value::Null), "count": obj.remove("cnt").unwrap_or(Value::Null) })
+            })
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "value_counts": value_counts }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Random sample of up to count rows; ORDER BY RANDOM() scans the whole table, \
+                          fast picks random rowids instead and may return fewer rows; returns { rows, row_count, truncated, limit_applied }")]
+    pub async fn sqlite_sample(&self, params: Parameters<SampleInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        let cols = match input.columns.as_ref().filter(|l| !l.is_empty()) {
+            Some(list) => {
+                for c in list { if !is_valid_ident(&state.ident_re, c) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); } }
+                list.join(", ")
+            }
+            None => "*".to_string(),
+        };
+        let count = input.count.clamp(1, state.row_limit("sqlite_sample", usize::MAX).min(i64::MAX as usize) as i64);
+        let fast = input.fast.unwrap_or(false) && stable_order_by(&state.pool, &input.table).await?.as_deref() == Some("rowid");
+        let q = if fast {
+            // Draws 2x candidate rowids between MIN and MAX; gaps and repeats can leave fewer than count hits
+            let sql = format!(
+                "WITH RECURSIVE lo_hi(lo, hi) AS (SELECT MIN(rowid), MAX(rowid) FROM {t}), \
+                 pick(n, id) AS (SELECT 0, NULL UNION ALL SELECT n + 1, lo + abs(random() % (hi - lo + 1)) FROM pick, lo_hi WHERE n < ?) \
+                 SELECT {c} FROM {t} WHERE rowid IN (SELECT id FROM pick) LIMIT ?",
+                t = input.table, c = cols);
+            sqlx::query(&sql).bind(count.saturating_mul(2)).bind(count).fetch_all(&state.pool).await
+        } else {
+            let sql = format!("SELECT {} FROM {} ORDER BY RANDOM() LIMIT ?", cols, input.table);
+            sqlx::query(&sql).bind(count).fetch_all(&state.pool).await
+        };
+        let rows = match q {
+            Ok(rows) => rows,
+            Err(e) => return Err(query_error(state, &input.table, e).await),
+        };
+        let rows = rows.iter()
+            .map(|r| { let mut obj = row_to_json(r, &[]); state.mask_row(&table, &mut obj); obj })
+            .collect::<Vec<_>>();
+        let content = Content::json(SelectedRows { rows, truncated: false, limit_applied: Some(count) }.into_json())
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Compare two rows of a table by id; masked columns are left out; \
+                          returns { differences: [{ column, value_a, value_b }], matching_columns }")]
+    pub async fn sqlite_row_diff(&self, params: Parameters<RowDiffInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.id_column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.id_column), None)); }
+        let sql = format!("SELECT * FROM {} WHERE {} = ? LIMIT 1", input.table, input.id_column);
+        let mut rows = Vec::with_capacity(2);
+        for id in [&input.id_a, &input.id_b] {
+            let q = bind_value(sqlx::query(&sql), id.clone()).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?;
+            let row = match q.fetch_optional(&state.pool).await {
+                Ok(row) => row,
+                Err(e) => return Err(query_error(state, &input.table, e).await),
+            };
+            let Some(row) = row else {
+                return Err(ErrorData::invalid_params(format!("No row in {} where {} = {}", input.table, input.id_column, id), None));
+            };
+            let mut obj = row_to_json(&row, &[]);
+            obj.retain(|c, _| !state.is_masked(&table, c));
+            rows.push(obj);
+        }
+        let (mut a, mut b) = (rows.remove(0), rows.remove(0));
+        let mut differences = Vec::new();
+        let mut matching_columns = 0usize;
+        for (column, value_a) in a.iter_mut() {
+            let value_b = b.remove(column).unwrap_or(Value::Null);
+            if *value_a == value_b { matching_columns += 1; }
+            else { differences.push(serde_json::json!({ "column": column, "value_a": value_a.take(), "value_b": value_b })); }
+        }
+        let content = Content::json(serde_json::json!({ "differences": differences, "matching_columns": matching_columns }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Delete every row of a table, optionally resetting its AUTOINCREMENT sequence; \
+                          Warp's own tables need force_protected; returns { rows_deleted }")]
+    pub async fn sqlite_truncate(&self, params: Parameters<TruncateInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (schema, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if PROTECTED_TABLES.iter().any(|p| p.eq_ignore_ascii_case(&table)) && !input.force_protected {
+            return Err(ErrorData::invalid_params(format!("{} is a protected table; set force_protected to truncate it", input.table), None));
+        }
+        let sql = format!("DELETE FROM {}", input.table);
+        // Each schema keeps its own sqlite_sequence
+        let schema = schema.as_deref().unwrap_or("main");
+        let sequence_sql = format!("DELETE FROM {}.sqlite_sequence WHERE name = ?1", schema);
+        if input.dry_run {
+            let sql = if input.reset_sequence { format!("{};\n{}", sql, sequence_sql) } else { sql };
+            return dry_run_result(&sql, usize::from(input.reset_sequence));
+        }
+        let mut tx = state.pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = sqlx::query(&sql).execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        if input.reset_sequence {
+            // sqlite_sequence only exists once some table uses AUTOINCREMENT
+            let has_sequence_sql = format!("SELECT 1 FROM {}.sqlite_master WHERE type = 'table' AND name = 'sqlite_sequence'", schema);
+            let has_sequence: Option<i64> = sqlx::query_scalar(&has_sequence_sql)
+                .fetch_optional(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            if has_sequence.is_some() {
+                sqlx::query(&sequence_sql)
+                    .bind(&table)
+                    .execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            }
+        }
+        tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_deleted": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Check a WHERE clause without running the full query; returns { valid: true, matches } or { valid: false, error }")]
+    pub async fn sqlite_match_count(&self, params: Parameters<MatchCountInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        let binds = input.params.unwrap_or_default();
+        state.check_params(binds.len())?;
+        let body = match count_where(&state.pool, &input.table, Some(&input.r#where), binds).await {
+            Ok(n) => serde_json::json!({ "valid": true, "matches": n }),
+            Err(e) => serde_json::json!({ "valid": false, "error": e.to_string() }),
+        };
+        let content = Content::json(body).map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Whether a table has a column (false too when the table doesn't exist); returns { exists }")]
+    pub async fn sqlite_has_column(&self, params: Parameters<HasColumnInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (schema, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        // table_xinfo so generated columns count too
+        let found: Option<i64> = sqlx::query_scalar("SELECT 1 FROM pragma_table_xinfo(?1, ?3) WHERE name = ?2 COLLATE NOCASE")
+            .bind(&table)
+            .bind(&input.column)
+            .bind(&schema)
+            .fetch_optional(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "exists": found.is_some() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Build a parameterized WHERE clause from structured conditions joined by AND (default) or OR, without running SQL; \
+                          returns { where, params } ready to pass to sqlite_select/update/delete")]
+    pub async fn sqlite_where_builder(&self, params: Parameters<WhereBuilderInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        const OPERATORS: &[&str] = &["=", "!=", "<", "<=", ">", ">=", "LIKE", "NOT LIKE", "IS NULL", "IS NOT NULL"];
+        let input = params.0;
+        if input.conditions.is_empty() { return Err(ErrorData::invalid_params("conditions must not be empty".to_string(), None)); }
+        let combinator = match input.combinator.as_deref().map(|c| c.to_ascii_uppercase()).as_deref() {
+            None | Some("AND") => " AND ",
+            Some("OR") => " OR ",
+            Some(other) => return Err(ErrorData::invalid_params(format!("Invalid combinator: {} (expected AND or OR)", other), None)),
+        };
+        let mut clauses = Vec::new();
+        let mut binds = Vec::new();
+        for cond in input.conditions {
+            if !is_valid_ident(&self.state.ident_re, &cond.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", cond.column), None)); }
+            let op = cond.operator.trim().to_ascii_uppercase();
+            if !OPERATORS.contains(&op.as_str()) { return Err(ErrorData::invalid_params(format!("Invalid operator: {}", cond.operator), None)); }
+            if op.starts_with("IS ") {
+                clauses.push(format!("{} {}", cond.column, op));
+            } else {
+                clauses.push(format!("{} {} ?", cond.column, op));
+                binds.push(cond.value);
+            }
+        }
+        let content = Content::json(serde_json::json!({ "where": clauses.join(combinator), "params": binds }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Atomically add `by` (default 1) to a numeric column, treating NULL as 0; returns { new_values, affected_row_count }")]
+    pub async fn sqlite_increment(&self, params: Parameters<IncrementInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_increment:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        let mut sql = format!("UPDATE {t} SET {c} = COALESCE({c}, 0) + ?", t = input.table, c = input.column);
+        if let Some(w) = &input.r#where { sql.push_str(" WHERE "); sql.push_str(&strip_sql_comments(w)); }
+        sql.push_str(&format!(" RETURNING {}", input.column));
+        let param_count = 1 + input.params.as_ref().map_or(0, |p| p.len());
+        state.check_params(param_count)?;
+        if input.dry_run { return dry_run_result(&sql, param_count); }
+        let mut q = sqlx::query(&sql).bind(input.by.unwrap_or(1));
+        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
+        let rows = q.fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let masked = state.is_masked(&table, &input.column);
+        let new_values = rows.iter()
+            .map(|r| if masked { Value::from("***") } else { row_to_json(r, &[]).remove(&input.column).unwrap_or(Value::Null) })
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "new_values": new_values, "affected_row_count": rows.len() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Copy rows with INSERT INTO dest (cols) SELECT cols FROM source [WHERE ...]; returns { rows_inserted }")]
+    pub async fn sqlite_insert_from_select(&self, params: Parameters<InsertFromSelectInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        for t in [&input.dest_table, &input.source_table] {
+            let (_, table) = parse_qualified_ident(&state.ident_re, t)?;
+            check_table_access(state, &table)?;
+        }
+        let (_, source) = parse_qualified_ident(&state.ident_re, &input.source_table)?;
+        let mut sql = match input.columns.as_ref().filter(|l| !l.is_empty()) {
+            Some(list) => {
+                for c in list { if !is_valid_ident(&state.ident_re, c) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); } }
+                check_not_masked(state, &source, Some(&list.iter().map(String::as_str).collect::<Vec<_>>()))?;
+                let cols = list.join(", ");
+                format!("INSERT INTO {} ({}) SELECT {} FROM {}", input.dest_table, cols, cols, input.source_table)
+            }
+            None => {
+                check_not_masked(state, &source, None)?;
+                format!("INSERT INTO {} SELECT * FROM {}", input.dest_table, input.source_table)
+            }
+        };
+        if let Some(w) = &input.r#where { sql.push_str(" WHERE "); sql.push_str(&strip_sql_comments(w)); }
+        let param_count = input.params.as_ref().map_or(0, |p| p.len());
+        state.check_params(param_count)?;
+        if input.dry_run { return dry_run_result(&sql, param_count); }
+        let mut q = sqlx::query(&sql);
+        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
+        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_inserted": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Copy rows between tables renaming columns: INSERT INTO dest (mapped) SELECT source cols FROM source [WHERE ...]; \
+                          column_map maps source column -> destination column; returns { rows_copied }")]
+    pub async fn sqlite_copy_rows(&self, params: Parameters<CopyRowsInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        for t in [&input.dest_table, &input.source_table] {
+            let (_, table) = parse_qualified_ident(&state.ident_re, t)?;
+            check_table_access(state, &table)?;
+        }
+        if input.column_map.is_empty() { return Err(ErrorData::invalid_params("column_map must not be empty".to_string(), None)); }
+        let mut src_cols = Vec::new();
+        let mut dest_cols = Vec::new();
+        for (src, dest) in input.column_map.iter() {
+            for c in [src, dest] {
+                if !is_valid_ident(&state.ident_re, c) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); }
+            }
+            src_cols.push(src.as_str());
+            dest_cols.push(dest.as_str());
+        }
+        let (_, source) = parse_qualified_ident(&state.ident_re, &input.source_table)?;
+        check_not_masked(state, &source, Some(&src_cols))?;
+        let mut sql = format!("INSERT INTO {} ({}) SELECT {} FROM {}", input.dest_table, dest_cols.join(", "), src_cols.join(", "), input.source_table);
+        if let Some(w) = &input.r#where { sql.push_str(" WHERE "); sql.push_str(&strip_sql_comments(w)); }
+        let param_count = input.params.as_ref().map_or(0, |p| p.len());
+        state.check_params(param_count)?;
+        if input.dry_run { return dry_run_result(&sql, param_count); }
+        let mut q = sqlx::query(&sql);
+        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
+        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_copied": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Copy all rows of source_table into dest_table, which must have the same columns in the same order, \
+                          with INSERT OR <conflict_action> (default ignore); returns { rows_inserted, rows_skipped }")]
+    pub async fn sqlite_merge_tables(&self, params: Parameters<MergeTablesInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        for t in [&input.dest_table, &input.source_table] {
+            if !is_valid_ident(&state.ident_re, t) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+            check_table_access(state, t)?;
+        }
+        check_not_masked(state, &input.source_table, None)?;
+        let action = input.conflict_action.as_deref().unwrap_or("ignore").to_ascii_uppercase();
+        if !["IGNORE", "REPLACE", "ABORT", "FAIL", "ROLLBACK"].contains(&action.as_str()) {
+            return Err(ErrorData::invalid_params(format!("Invalid conflict_action: {}", action.to_lowercase()), None));
+        }
+        let mut tx = state.pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        // SELECT * is positional, so the column lists must match exactly
+        let mut columns = Vec::new();
+        for t in [&input.source_table, &input.dest_table] {
+            let cols: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?1) ORDER BY cid")
+                .bind(t)
+                .fetch_all(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            if cols.is_empty() { return Err(ErrorData::invalid_params(format!("No such table: {}", t), None)); }
+            columns.push(cols);
+        }
+        if columns[0] != columns[1] {
+            return Err(ErrorData::invalid_params(
+                "Tables have different columns".to_string(),
+                Some(serde_json::json!({ "source_columns": columns[0], "dest_columns": columns[1] })),
+            ));
+        }
+        let sql = format!("INSERT OR {} INTO {} SELECT * FROM {}", action, input.dest_table, input.source_table);
+        if input.dry_run { return dry_run_result(&sql, 0); }
+        let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", input.source_table))
+            .fetch_one(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = sqlx::query(&sql)
+            .execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let rows_inserted = res.rows_affected();
+        let content = Content::json(serde_json::json!({ "rows_inserted": rows_inserted, "rows_skipped": (total as u64).saturating_sub(rows_inserted) }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Swap the names of two tables in one transaction (A -> temp, B -> A, temp -> B), \
+                          for the populate-a-shadow-table-then-swap pattern; returns { ok }")]
+    pub async fn sqlite_swap_tables(&self, params: Parameters<SwapTablesInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        for t in [&input.table_a, &input.table_b] {
+            if !is_valid_ident(&state.ident_re, t) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+            check_table_access(state, t)?;
+            // Masks follow the name, so either table's rows would end up under the other, unmasked name
+            check_not_masked(state, t, None)?;
+        }
+        if input.table_a.eq_ignore_ascii_case(&input.table_b) { return Err(ErrorData::invalid_params("table_a and table_b must differ".to_string(), None)); }
+        let temp = format!("{}__swap_tmp", input.table_a);
+        let statements = [(&input.table_a, &temp), (&input.table_b, &input.table_a), (&temp, &input.table_b)]
+            .map(|(from, to)| format!("ALTER TABLE {} RENAME TO {}", from, to));
+        if input.dry_run { return dry_run_result(&statements.join(";\n"), 0); }
+        let mut tx = state.pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        for sql in &statements {
+            sqlx::query(sql).execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        }
+        tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "ok": true }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Delete rows whose pk_column is in pk_values (chunked, in one transaction); returns { rows_deleted }, \
+                          plus per-key results with continue_on_error")]
+    pub async fn sqlite_delete_batch(&self, params: Parameters<BatchDeleteInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_delete_batch:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.pk_column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.pk_column), None)); }
+        let statements = input.pk_values.chunks(state.max_params)
+            .map(|chunk| (format!("DELETE FROM {} WHERE {} IN ({})", input.table, input.pk_column, vec!["?"; chunk.len()].join(", ")), chunk))
+            .collect::<Vec<_>>();
+        let per_key_sql = format!("DELETE FROM {} WHERE {} = ?", input.table, input.pk_column);
+        if input.dry_run {
+            let sql = if input.continue_on_error { per_key_sql } else { statements.iter().map(|(sql, _)| sql.as_str()).collect::<Vec<_>>().join(";\n") };
+            return dry_run_result(&sql, input.pk_values.len());
+        }
+        let mut tx = begin_batch(&state.pool, input.mode.as_deref()).await?;
+        let out = if input.continue_on_error {
+            let (rows_deleted, results) = run_per_key(&mut tx, &per_key_sql, &[], &input.pk_values).await?;
+            serde_json::json!({ "rows_deleted": rows_deleted, "results": results })
+        } else {
+            let mut rows_deleted = 0u64;
+            for (sql, chunk) in statements {
+                let mut q = sqlx::query(&sql);
+                for v in chunk { q = bind_value(q, v.clone()).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+                let res = q.execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+                rows_deleted += res.rows_affected();
+            }
+            serde_json::json!({ "rows_deleted": rows_deleted })
+        };
+        tx.commit().await?;
+        let content = Content::json(out)
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Apply the same set of column values to all rows whose pk_column is in pk_values \
+                          (chunked, in one transaction); returns { rows_affected }, plus per-key results with continue_on_error")]
+    pub async fn sqlite_update_where_in(&self, params: Parameters<UpdateWhereInInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("sqlite_update_where_in:{}", k));
+        let claim = match claim_key(state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        if !is_valid_ident(&state.ident_re, &input.pk_column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.pk_column), None)); }
+        if input.set.is_empty() { return Err(ErrorData::invalid_params("No columns provided in set".to_string(), None)); }
+        let mut frags = Vec::new();
+        for k in input.set.keys() {
+            if !is_valid_ident(&state.ident_re, k) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", k), None)); }
+            frags.push(format!("{} = ?", k));
+        }
+        // SET values and the IN list share the bind parameter budget
+        let chunk_size = state.max_params.saturating_sub(input.set.len());
+        if chunk_size == 0 { return Err(ErrorData::invalid_params("Too many columns in set".to_string(), None)); }
+        let statements = input.pk_values.chunks(chunk_size)
+            .map(|chunk| {
+                let placeholders = vec!["?"; chunk.len()].join(", ");
+                (format!("UPDATE {} SET {} WHERE {} IN ({})", input.table, frags.join(", "), input.pk_column, placeholders), chunk)
+            })
+            .collect::<Vec<_>>();
+        let per_key_sql = format!("UPDATE {} SET {} WHERE {} = ?", input.table, frags.join(", "), input.pk_column);
+        if input.dry_run {
+            if input.continue_on_error { return dry_run_result(&per_key_sql, input.pk_values.len() * (input.set.len() + 1)); }
+            let sql = statements.iter().map(|(sql, _)| sql.as_str()).collect::<Vec<_>>().join(";\n");
+            return dry_run_result(&sql, statements.len() * input.set.len() + input.pk_values.len());
+        }
+        let mut tx = begin_batch(&state.pool, input.mode.as_deref()).await?;
+        let out = if input.continue_on_error {
+            let set = input.set.values().cloned().collect::<Vec<_>>();
+            let (rows_affected, results) = run_per_key(&mut tx, &per_key_sql, &set, &input.pk_values).await?;
+            serde_json::json!({ "rows_affected": rows_affected, "results": results })
+        } else {
+            let mut rows_affected = 0u64;
+            for (sql, chunk) in statements {
+                let mut q = sqlx::query(&sql);
+                for v in input.set.values().chain(chunk) { q = bind_value(q, v.clone()).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+                let res = q.execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+                rows_affected += res.rows_affected();
+            }
+            serde_json::json!({ "rows_affected": rows_affected })
+        };
+        tx.commit().await?;
+        let content = Content::json(out)
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    // ---- MCP management tools ----
+    #[tool(description = "Register an MCP server UUID in active_mcp_servers (idempotent)")]
+    pub async fn mcp_register_server(&self, params: Parameters<McpRegisterInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let sql = "INSERT OR IGNORE INTO active_mcp_servers (mcp_server_uuid) VALUES (?1)";
+        if input.dry_run { return dry_run_result(sql, 1); }
+        let res = sqlx::query(sql)
+            .bind(input.mcp_server_uuid)
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Unregister an MCP server UUID from active_mcp_servers")]
+    pub async fn mcp_unregister_server(&self, params: Parameters<McpUnregisterInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let sql = "DELETE FROM active_mcp_servers WHERE mcp_server_uuid = ?1";
+        if input.dry_run { return dry_run_result(sql, 1); }
+        let res = sqlx::query(sql)
+            .bind(input.mcp_server_uuid)
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Set environment variables JSON for an MCP server UUID (upsert)")]
+    pub async fn mcp_set_env(&self, params: Parameters<McpSetEnvInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let env_text = serde_json::to_string(&input.env).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?;
+        let sql = "INSERT INTO mcp_environment_variables (mcp_server_uuid, environment_variables) VALUES (?1, ?2) \
+                   ON CONFLICT(mcp_server_uuid) DO UPDATE SET environment_variables=excluded.environment_variables";
+        if input.dry_run { return dry_run_result(sql, 2); }
+        let res = sqlx::query(sql)
+            .bind(input.mcp_server_uuid)
+            .bind(env_text)
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Get environment variables JSON for an MCP server UUID; env is null when there is no row. \
+                          If the stored value is not valid JSON, returns { env: null, error, raw } with the stored string")]
+    pub async fn mcp_get_env(&self, params: Parameters<McpGetEnvInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let sql = "SELECT environment_variables FROM mcp_environment_variables WHERE mcp_server_uuid = ?1";
+        let row = sqlx::query(sql)
+            .bind(input.mcp_server_uuid)
+            .fetch_optional(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let masked = self.state.is_masked("mcp_environment_variables", "environment_variables");
+        let mut val = Value::Null;
+        if let Some(r) = row {
+            let s: String = r.try_get(0).unwrap_or_default();
+            match serde_json::from_str::<Value>(&s) {
+                Ok(v) => val = v,
+                Err(_) => {
+                    // Surface corrupt rows instead of reporting them as missing; the raw text may hold secrets, so respect masking
+                    let raw = if masked { "***".to_string() } else { s };
+                    let content = Content::json(serde_json::json!({ "env": Value::Null, "error": "stored env is not valid JSON", "raw": raw }))
+                        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+                    return Ok(CallToolResult::success(vec![content]));
+                }
+            }
+        }
+        if masked {
+            if let Value::Object(env) = &mut val {
+                for (k, v) in env.iter_mut() {
+                    let upper = k.to_ascii_uppercase();
+                    if SENSITIVE_ENV_KEY_PARTS.iter().any(|p| upper.contains(p)) { *v = Value::from("***"); }
+                }
+            }
+        }
+        let content = Content::json(serde_json::json!({ "env": val }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    // ---- Notebook tools ----
+    #[tool(description = "Create a notebook with title and body; returns { id }")]
+    pub async fn notebook_create(&self, params: Parameters<NotebookCreateInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("notebook_create:{}", k));
+        let claim = match claim_key(&self.state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let title = input.title.unwrap_or_else(|| "".to_string());
+        let sql = "INSERT INTO notebooks (title, data) VALUES (?1, ?2)";
+        if input.dry_run { return dry_run_result(sql, 2); }
+        let res = sqlx::query(sql)
+            .bind(title)
+            .bind(input.body)
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "id": res.last_insert_rowid() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Append delta text to a notebook's body, with an optional separator when the body is non-empty \
+                          and ensure_trailing_newline to end delta with \\n; returns rows_affected")]
+    pub async fn notebook_append(&self, params: Parameters<NotebookAppendInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let cache_key = input.idempotency_key.as_ref().map(|k| format!("notebook_append:{}", k));
+        let claim = match claim_key(&self.state, cache_key).await? { Claim::Replay(res) => return Ok(res), Claim::Owned(c) => c };
+        let sql = "UPDATE notebooks SET data = CASE WHEN data IS NULL OR data = '' THEN ?1 ELSE data || ?3 || ?1 END WHERE id = ?2";
+        if input.dry_run { return dry_run_result(sql, 3); }
+        let mut delta = input.delta;
+        if input.ensure_trailing_newline.unwrap_or(false) && !delta.ends_with('\n') { delta.push('\n'); }
+        let res = sqlx::query(sql)
+            .bind(delta)
+            .bind(input.id)
+            .bind(input.separator.unwrap_or_default())
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let res = CallToolResult::success(vec![content]);
+        claim.complete(&res).await;
+        Ok(res)
+    }
+
+    #[tool(description = "Delete a notebook by id; returns rows_affected")]
+    pub async fn notebook_delete(&self, params: Parameters<NotebookDeleteInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let sql = "DELETE FROM notebooks WHERE id = ?1";
+        if input.dry_run { return dry_run_result(sql, 1); }
+        let res = sqlx::query(sql)
+            .bind(input.id)
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Delete up to 100 notebooks by id in one transaction, along with their notebook_versions rows when that table exists; \
+                          returns { rows_deleted, deleted_ids, versions_deleted }")]
+    pub async fn notebook_bulk_delete(&self, params: Parameters<NotebookBulkDeleteInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        if input.ids.is_empty() { return Err(ErrorData::invalid_params("ids must not be empty".to_string(), None)); }
+        if input.ids.len() > 100 { return Err(ErrorData::invalid_params("At most 100 ids per call".to_string(), None)); }
+        let placeholders = vec!["?"; input.ids.len()].join(", ");
+        let filter = format!(" WHERE id IN ({})", placeholders);
+        let versions_sql = format!("DELETE FROM {} WHERE notebook_id IN ({})", NOTEBOOK_VERSIONS_TABLE, placeholders);
+        let mut tx = self.state.pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        // Versions are optional; cascade only when this database has the table
+        let has_versions: Option<i64> = sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")
+            .bind(NOTEBOOK_VERSIONS_TABLE)
+            .fetch_optional(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        if input.dry_run {
+            let delete = format!("DELETE FROM notebooks{}", filter);
+            let sql = if has_versions.is_some() { format!("{};\n{}", versions_sql, delete) } else { delete };
+            return dry_run_result(&sql, input.ids.len());
+        }
+        let mut versions_deleted = 0;
+        if has_versions.is_some() {
+            let mut q = sqlx::query(&versions_sql);
+            for id in &input.ids { q = q.bind(*id); }
+            versions_deleted = q.execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?.rows_affected();
+        }
+        let binds = input.ids.iter().map(|id| Value::from(*id)).collect();
+        let deleted_ids = delete_returning(&mut tx, "notebooks", "id", &filter, binds).await?;
+        tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_deleted": deleted_ids.len(), "deleted_ids": deleted_ids, "versions_deleted": versions_deleted }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Append one notebook's body to another (separator only when the target is non-empty), \
+                          optionally deleting the source, in one transaction; returns { target_length, source_deleted }")]
+    pub async fn notebook_merge(&self, params: Parameters<NotebookMergeInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        if input.source_id == input.target_id { return Err(ErrorData::invalid_params("source_id and target_id must differ".to_string(), None)); }
+        let merge_sql = "UPDATE notebooks SET data = CASE WHEN data IS NULL OR data = '' THEN ?1 ELSE data || ?2 || ?1 END WHERE id = ?3 RETURNING length(data)";
+        let delete_sql = "DELETE FROM notebooks WHERE id = ?1";
+        let source_deleted = input.delete_source.unwrap_or(false);
+        if input.dry_run {
+            return if source_deleted { dry_run_result(&format!("{};\n{}", merge_sql, delete_sql), 4) } else { dry_run_result(merge_sql, 3) };
+        }
+        let mut tx = self.state.pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let source: Option<Option<String>> = sqlx::query_scalar("SELECT data FROM notebooks WHERE id = ?1")
+            .bind(input.source_id)
+            .fetch_optional(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let Some(source) = source else { return Err(ErrorData::invalid_params(format!("Notebook {} not found", input.source_id), None)) };
+        let target_length: Option<i64> = sqlx::query_scalar(merge_sql)
+            .bind(source.unwrap_or_default())
+            .bind(input.separator.unwrap_or_default())
+            .bind(input.target_id)
+            .fetch_optional(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let Some(target_length) = target_length else { return Err(ErrorData::invalid_params(format!("Notebook {} not found", input.target_id), None)) };
+        if source_deleted {
+            sqlx::query(delete_sql)
+                .bind(input.source_id)
+                .execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        }
+        tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "target_length": target_length, "source_deleted": source_deleted }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "List notebooks with optional query on title/body; returns id,title,snippet,char_count")]
+    pub async fn notebook_list(&self, params: Parameters<NotebookListInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let limit = input.limit.unwrap_or(50).clamp(1, self.state.row_limit("notebook_list", 500) as i64);
+        let offset = input.offset.unwrap_or(0).max(0);
+        let snippet_length = input.snippet_length.unwrap_or(200).clamp(1, 2000);
+        let from_match = input.snippet_from_match.unwrap_or(false);
+        let (sql, bind_query) = if let Some(q) = input.query {
+            // Start half a snippet before the first case-insensitive match; title-only matches start at the beginning
+            let start = if from_match {
+                "CASE WHEN instr(lower(data), lower(?6)) > 0 THEN max(1, instr(lower(data), lower(?6)) - ?5 / 2) ELSE 1 END"
+            } else { "1" };
+            (format!("SELECT id, title, substr(data,{},?5) AS snippet, length(data) AS char_count FROM notebooks WHERE (title LIKE ?1 OR data LIKE ?2) ORDER BY id DESC LIMIT ?3 OFFSET ?4", start), Some(q))
+        } else {
+            ("SELECT id, title, substr(data,1,?3) AS snippet, length(data) AS char_count FROM notebooks ORDER BY id DESC LIMIT ?1 OFFSET ?2".to_string(), None)
+        };
+        let rows = if let Some(q) = bind_query {
+            let like = format!("%{}%", q);
+            let mut query = sqlx::query(&sql)
+                .bind(like.clone())
+                .bind(like)
+                .bind(limit)
+                .bind(offset)
+                .bind(snippet_length);
+            // ?6 only appears in the match-centered snippet
+            if from_match { query = query.bind(q); }
+            query.fetch_all(&self.state.pool)
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+        } else {
+            sqlx::query(&sql)
+                .bind(limit)
+                .bind(offset)
+                .bind(snippet_length)
+                .fetch_all(&self.state.pool)
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+        };
+        let out = rows.iter().map(|r| self.state.notebook_item(r)).collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "items": out }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Most recently modified notebooks, by updated_at (newest id when the table has no updated_at column); \
+                          returns { items: [{ id, title, snippet, updated_at }] }")]
+    pub async fn notebook_recent(&self, params: Parameters<NotebookRecentInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let limit = params.0.limit.unwrap_or(10).clamp(1, self.state.row_limit("notebook_recent", 100) as i64);
+        let has_updated_at: Option<i64> = sqlx::query_scalar("SELECT 1 FROM pragma_table_info('notebooks') WHERE name = 'updated_at'")
+            .fetch_optional(&self.state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let sql = if has_updated_at.is_some() {
+            "SELECT id, title, substr(data,1,200) AS snippet, updated_at FROM notebooks ORDER BY updated_at DESC, id DESC LIMIT ?1"
+        } else {
+            "SELECT id, title, substr(data,1,200) AS snippet, NULL AS updated_at FROM notebooks ORDER BY id DESC LIMIT ?1"
+        };
+        let rows = sqlx::query(sql)
+            .bind(limit)
+            .fetch_all(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let items = rows.iter()
+            .map(|r| {
+                let mut obj = row_to_json(r, &[]);
+                let mut item = serde_json::json!({
+                    "id": obj.remove("id").unwrap_or(Value::Null),
+                    "title": obj.remove("title").unwrap_or(Value::Null),
+                    "snippet": obj.remove("snippet").unwrap_or(Value::Null),
+                    "updated_at": obj.remove("updated_at").unwrap_or(Value::Null),
+                });
+                self.state.mask_notebook(&mut item);
+                item
+            })
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "items": items }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Search notebooks for whitespace-separated words in title or body, case-insensitively; \
+                          match_all requires every word, otherwise any word; returns id,title,snippet,char_count")]
+    pub async fn notebook_word_search(&self, params: Parameters<NotebookWordSearchInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let words = input.query.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() { return Err(ErrorData::invalid_params("query must contain at least one word".to_string(), None)); }
+        self.state.check_params(words.len() * 2)?;
+        let limit = input.limit.unwrap_or(50).clamp(1, self.state.row_limit("notebook_word_search", 500) as i64);
+        let offset = input.offset.unwrap_or(0).max(0);
+        let conds = vec!["(upper(title) LIKE upper(?) OR upper(data) LIKE upper(?))"; words.len()]
+            .join(if input.match_all { " AND " } else { " OR " });
+        let sql = format!(
+            "SELECT id, title, substr(data,1,200) AS snippet, length(data) AS char_count FROM notebooks WHERE {} ORDER BY id DESC LIMIT {} OFFSET {}",
+            conds, limit, offset,
+        );
+        let mut q = sqlx::query(&sql);
+        for w in &words {
+            let like = format!("%{}%", w);
+            q = q.bind(like.clone()).bind(like);
+        }
+        let rows = q.fetch_all(&self.state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let out = rows.iter().map(|r| self.state.notebook_item(r)).collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "items": out }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Fetch several notebooks by id in one call; returns items shaped like notebook_list, plus data when include_body")]
+    pub async fn notebook_list_by_ids(&self, params: Parameters<NotebookListByIdsInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        // Descending ids chunked in order keep the combined result sorted like notebook_list
+        let mut ids = input.ids;
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        ids.dedup();
+        let mut rows = Vec::new();
+        for chunk in ids.chunks(self.state.max_params) {
+            let sql = format!(
+                "SELECT id, title, substr(data,1,200) AS snippet, length(data) AS char_count{} FROM notebooks WHERE id IN ({}) ORDER BY id DESC",
+                if input.include_body { ", data" } else { "" },
+                vec!["?"; chunk.len()].join(", "),
+            );
+            let mut q = sqlx::query(&sql);
+            for id in chunk { q = q.bind(id); }
+            rows.extend(q.fetch_all(&self.state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?);
+        }
+        let out = rows.iter().map(|r| self.state.notebook_item(r)).collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "items": out }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Get a notebook by id; returns full row plus char_count and word_count")]
+    pub async fn notebook_get(&self, params: Parameters<NotebookGetInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let sql = "SELECT id, title, data, length(data) AS char_count FROM notebooks WHERE id = ?1";
+        let row = sqlx::query(sql)
+            .bind(input.id)
+            .fetch_optional(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let mut val = if let Some(r) = row {
+            let id: i64 = r.try_get("id").unwrap_or_default();
+            let title: String = r.try_get("title").unwrap_or_default();
+            let data: String = r.try_get("data").unwrap_or_default();
+            let char_count: i64 = r.try_get("char_count").unwrap_or_default();
+            serde_json::json!({"id": id, "title": title, "data": data, "char_count": char_count, "word_count": data.split_whitespace().count()})
+        } else { serde_json::json!({}) };
+        self.state.mask_notebook(&mut val);
+        json_result(&self.state, val, input.compress.unwrap_or(false))
+    }
+
+    #[tool(description = "Tag a notebook (no-op if already tagged); returns { rows_affected }")]
+    pub async fn notebook_tag_add(&self, params: Parameters<NotebookTagAddInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        validate_tag(&input.tag)?;
+        let sql = "INSERT OR IGNORE INTO notebook_tags (notebook_id, tag) VALUES (?1, ?2)";
+        if input.dry_run { return dry_run_result(sql, 2); }
+        ensure_notebook_tags(&self.state.pool).await?;
+        let res = sqlx::query(sql)
+            .bind(input.id)
+            .bind(&input.tag)
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Remove a tag from a notebook; returns { rows_affected }")]
+    pub async fn notebook_tag_remove(&self, params: Parameters<NotebookTagRemoveInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        validate_tag(&input.tag)?;
+        let sql = "DELETE FROM notebook_tags WHERE notebook_id = ?1 AND tag = ?2";
+        if input.dry_run { return dry_run_result(sql, 2); }
+        ensure_notebook_tags(&self.state.pool).await?;
+        let res = sqlx::query(sql)
+            .bind(input.id)
+            .bind(&input.tag)
+            .execute(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
     }
-    "sqlite://./app.sqlite".to_string()
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    // DATABASE_URL example: sqlite:///Users/samuelatagana/Library/Application Support/dev.warp.Warp-Stable/warp.sqlite
-    let db_url = load_db_url();
 
-    let pool = SqlitePoolOptions::new().max_connections(5).connect(&db_url).await?;
-    // Best-effort WAL
-    let _ = sqlx::query("PRAGMA journal_mode = WAL;").execute(&pool).await;
+    #[tool(description = "Tags on a notebook, sorted; returns { tags }")]
+    pub async fn notebook_get_tags(&self, params: Parameters<NotebookGetTagsInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let tags: Vec<String> = if notebook_tags_exist(&self.state.pool).await? {
+            sqlx::query_scalar("SELECT tag FROM notebook_tags WHERE notebook_id = ?1 ORDER BY tag")
+                .bind(input.id)
+                .fetch_all(&self.state.pool)
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+        } else { Vec::new() };
+        let content = Content::json(serde_json::json!({ "tags": tags }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
 
-    let state = Arc::new(AppState {
-        pool,
-        ident_re: Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap(),
-    });
+    #[tool(description = "Every tag in use with how many notebooks carry it, most used first; returns { tags: [{ tag, notebook_count }] }")]
+    pub async fn notebook_list_all_tags(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let rows: Vec<(String, i64)> = if notebook_tags_exist(&self.state.pool).await? {
+            sqlx::query_as("SELECT tag, COUNT(*) AS notebook_count FROM notebook_tags GROUP BY tag ORDER BY notebook_count DESC, tag")
+                .fetch_all(&self.state.pool)
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+        } else { Vec::new() };
+        let tags = rows.into_iter()
+            .map(|(tag, n)| serde_json::json!({ "tag": tag, "notebook_count": n }))
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "tags": tags }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
 
-    let service = SqliteService { state, tool_router: SqliteService::tool_router() };
-    let server = service.serve(stdio()).await?;
-    server.waiting().await?;
-    Ok(())
-}
+    #[tool(description = "List notebooks carrying a tag; returns id,title,snippet,char_count")]
+    pub async fn notebook_list_by_tag(&self, params: Parameters<NotebookListByTagInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        validate_tag(&input.tag)?;
+        ensure_notebook_tags(&self.state.pool).await?;
+        let limit = input.limit.unwrap_or(50).clamp(1, self.state.row_limit("notebook_list_by_tag", 500) as i64);
+        let offset = input.offset.unwrap_or(0).max(0);
+        let rows = sqlx::query(
+            "SELECT n.id, n.title, substr(n.data,1,200) AS snippet, length(n.data) AS char_count \
+             FROM notebooks n JOIN notebook_tags t ON t.notebook_id = n.id \
+             WHERE t.tag = ?1 ORDER BY n.id DESC LIMIT ?2 OFFSET ?3")
+            .bind(&input.tag)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let out = rows.iter().map(|r| self.state.notebook_item(r)).collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "items": out }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
 
-#[derive(Clone)]
-struct SqliteService {
-    state: Arc<AppState>,
-    tool_router: ToolRouter<SqliteService>,
-}
+    // ---- Maintenance tools ----
+    #[tool(description = "Rebuild an FTS5 index after its content table was modified directly; returns { ok }")]
+    pub async fn sqlite_rebuild_fts(&self, params: Parameters<RebuildFtsInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        if !is_valid_ident(&state.ident_re, &input.fts_table) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+        let ddl: Option<String> = sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1")
+            .bind(&input.fts_table)
+            .fetch_optional(&state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+            .flatten();
+        let is_fts5 = ddl.map(|d| d.to_ascii_lowercase().contains("using fts5")).unwrap_or(false);
+        if !is_fts5 { return Err(ErrorData::invalid_params(format!("Not an FTS5 table: {}", input.fts_table), None)); }
+        let sql = format!("INSERT INTO {t}({t}) VALUES('rebuild')", t = input.fts_table);
+        if input.dry_run { return dry_run_result(&sql, 0); }
+        sqlx::query(&sql).execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "ok": true }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
 
-#[tool_router]
-impl SqliteService {
-    #[tool(description = "Insert a row; returns last_insert_rowid")]
-    pub async fn sqlite_insert(&self, params: Parameters<InsertInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Rebuild indexes with REINDEX, for all indexes or one index/table. Needed after a collation's definition changes \
+                          (e.g. SQLite upgrade) or when integrity_check reports index corruption. With analyze, also runs ANALYZE \
+                          to refresh planner statistics after bulk loads; returns { ok, elapsed_ms, analyzed }")]
+    pub async fn sqlite_reindex(&self, params: Parameters<ReindexInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
         let state = &self.state;
-        if !is_valid_ident(&state.ident_re, &input.table) {
-            return Err(ErrorData::invalid_params("Invalid table name".to_string(), None));
-        }
-        let mut cols = Vec::new();
-        let mut binds = Vec::new();
-        for (k, v) in input.values.iter() {
-            if !is_valid_ident(&state.ident_re, k) {
-                return Err(ErrorData::invalid_params(format!("Invalid column: {}", k), None));
+        let target = match &input.index_or_table {
+            Some(name) => {
+                if !is_valid_ident(&state.ident_re, name) { return Err(ErrorData::invalid_params("Invalid index or table name".to_string(), None)); }
+                // Indexes are checked against the table they belong to
+                let table: Option<String> = sqlx::query_scalar("SELECT tbl_name FROM sqlite_master WHERE name = ?1")
+                    .bind(name)
+                    .fetch_optional(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+                check_table_access(state, table.as_deref().unwrap_or(name))?;
+                format!(" {}", name)
             }
-            cols.push(k.clone());
-            binds.push(v.clone());
+            None => String::new(),
+        };
+        if input.dry_run {
+            let sql = if input.analyze { format!("REINDEX{t};\nANALYZE{t}", t = target) } else { format!("REINDEX{}", target) };
+            return dry_run_result(&sql, 0);
         }
-        if cols.is_empty() {
-            return Err(ErrorData::invalid_params("No columns provided".to_string(), None));
+        let started = std::time::Instant::now();
+        sqlx::query(&format!("REINDEX{}", target)).execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        if input.analyze {
+            sqlx::query(&format!("ANALYZE{}", target)).execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         }
-        let placeholders = std::iter::repeat("?").take(cols.len()).collect::<Vec<_>>().join(", ");
-        let sql = format!("INSERT INTO {} ({}) VALUES ({})", input.table, cols.join(", "), placeholders);
-        let mut q = sqlx::query(&sql);
-        for v in binds { q = bind_value(q, v).map_err(|e| ErrorData::internal_error(e.to_string(), None))?; }
-        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "last_insert_rowid": res.last_insert_rowid() }))
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let content = Content::json(serde_json::json!({ "ok": true, "elapsed_ms": elapsed_ms, "analyzed": input.analyze }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "Select rows; returns rows array of objects")]
-    pub async fn sqlite_select(&self, params: Parameters<SelectInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Create <table><suffix> (default _history) with the table's columns plus _op and _changed_at (unix seconds), \
+                          and AFTER INSERT/UPDATE/DELETE triggers that log each change into it. Existing history tables and \
+                          triggers are kept; returns { history_table, triggers_created }")]
+    pub async fn sqlite_history_table(&self, params: Parameters<SetupHistoryTableInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
         let state = &self.state;
-        if !is_valid_ident(&state.ident_re, &input.table) {
-            return Err(ErrorData::invalid_params("Invalid table name".to_string(), None));
+        let history = format!("{}{}", input.table, input.history_table_suffix.as_deref().unwrap_or("_history"));
+        for t in [&input.table, &history] {
+            if !is_valid_ident(&state.ident_re, t) { return Err(ErrorData::invalid_params(format!("Invalid table name: {}", t), None)); }
+            check_table_access(state, t)?;
         }
-        let cols = if let Some(list) = &input.columns {
-            if list.is_empty() { "*".to_string() } else {
-                for c in list { if !is_valid_ident(&state.ident_re, c) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); } }
-                list.join(", ")
-            }
-        } else { "*".to_string() };
-        let mut sql = format!("SELECT {} FROM {}", cols, input.table);
-        if let Some(w) = &input.r#where { sql.push_str(" WHERE "); sql.push_str(w); }
-        if let Some(ob) = &input.order_by { sql.push_str(" ORDER BY "); sql.push_str(ob); }
-        if let Some(l) = input.limit { sql.push_str(&format!(" LIMIT {}", l)); }
-        if let Some(o) = input.offset { sql.push_str(&format!(" OFFSET {}", o)); }
-        let mut q = sqlx::query(&sql);
-        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
-        let rows = q.fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let mut out = Vec::<serde_json::Map<String, Value>>::new();
-        for row in rows {
-            let cols = row.columns();
-            let mut obj = serde_json::Map::new();
-            for col in cols {
-                let name = col.name().to_string();
-                let raw = row.try_get_raw(name.as_str());
-                let v = match raw {
-                    Ok(r) if r.is_null() => Value::Null,
-                    Ok(_) => {
-                        if let Ok(v) = row.try_get::<i64, _>(name.as_str()) { Value::from(v) }
-                        else if let Ok(v) = row.try_get::<f64, _>(name.as_str()) { Value::from(v) }
-                        else if let Ok(v) = row.try_get::<String, _>(name.as_str()) { Value::from(v) }
-                        else if let Ok(v) = row.try_get::<Vec<u8>, _>(name.as_str()) { Value::from(B64.encode(v)) }
-                        else { Value::Null }
-                    }
-                    Err(_) => Value::Null,
-                };
-                obj.insert(name, v);
-            }
-            out.push(obj);
+        check_not_masked(state, &input.table, None)?;
+        let columns: Vec<(String, String)> = sqlx::query_as("SELECT name, type FROM pragma_table_info(?1) ORDER BY cid")
+            .bind(&input.table)
+            .fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        if columns.is_empty() { return Err(ErrorData::invalid_params(format!("No such table: {}", input.table), None)); }
+        let quote = |c: &str| format!("\"{}\"", c.replace('"', "\"\""));
+        // Types only: the history table must accept repeated keys and NULLs the source would reject
+        let defs = columns.iter().map(|(c, ty)| format!("{} {}", quote(c), ty)).collect::<Vec<_>>().join(", ");
+        let names = columns.iter().map(|(c, _)| quote(c)).collect::<Vec<_>>().join(", ");
+        let mut statements = vec![format!("CREATE TABLE IF NOT EXISTS {} ({}, _op TEXT, _changed_at INTEGER)", history, defs)];
+        let mut triggers = Vec::new();
+        for (op, row, suffix) in [("INSERT", "NEW", "ai"), ("UPDATE", "NEW", "au"), ("DELETE", "OLD", "ad")] {
+            let name = format!("{}_{}", history, suffix);
+            let values = columns.iter().map(|(c, _)| format!("{}.{}", row, quote(c))).collect::<Vec<_>>().join(", ");
+            statements.push(format!(
+                "CREATE TRIGGER IF NOT EXISTS {name} AFTER {op} ON {t} BEGIN \
+                 INSERT INTO {history} ({names}, _op, _changed_at) VALUES ({values}, '{op}', CAST(strftime('%s', 'now') AS INTEGER)); END",
+                t = input.table));
+            triggers.push(name);
+        }
+        if input.dry_run { return dry_run_result(&statements.join(";\n"), 0); }
+        let mut tx = state.pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let mut triggers_created = 0usize;
+        for name in &triggers {
+            let exists: Option<i64> = sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE type = 'trigger' AND name = ?1")
+                .bind(name)
+                .fetch_optional(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            if exists.is_none() { triggers_created += 1; }
         }
-        let content = Content::json(serde_json::json!({ "rows": out }))
+        for sql in &statements {
+            sqlx::query(sql).execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        }
+        tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "history_table": history, "triggers_created": triggers_created }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "Update rows; returns affected_row_count")]
-    pub async fn sqlite_update(&self, params: Parameters<UpdateInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Create a table from column definitions (name, type INTEGER/REAL/TEXT/BLOB/NUMERIC, not_null, primary_key, \
+                          unique, default) without going through raw SQL; subject to the table allow/deny lists. Returns { ddl }")]
+    pub async fn sqlite_create_table(&self, params: Parameters<CreateTableInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        const COLUMN_TYPES: &[&str] = &["INTEGER", "REAL", "TEXT", "BLOB", "NUMERIC"];
         let input = params.0;
         let state = &self.state;
-        if !is_valid_ident(&state.ident_re, &input.table) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
-        if input.set.is_empty() { return Err(ErrorData::invalid_params("No columns provided in set".to_string(), None)); }
-        let mut frags = Vec::new();
-        let mut vals = Vec::new();
-        for (k, v) in input.set.iter() {
-            if !is_valid_ident(&state.ident_re, k) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", k), None)); }
-            frags.push(format!("{} = ?", k));
-            vals.push(v.clone());
+        if !is_valid_ident(&state.ident_re, &input.name) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+        check_table_access(state, &input.name)?;
+        if input.columns.is_empty() { return Err(ErrorData::invalid_params("No columns provided".to_string(), None)); }
+        let mut defs = Vec::with_capacity(input.columns.len());
+        for c in &input.columns {
+            if !is_valid_ident(&state.ident_re, &c.name) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c.name), None)); }
+            let ty = c.col_type.to_ascii_uppercase();
+            if !COLUMN_TYPES.contains(&ty.as_str()) {
+                return Err(ErrorData::invalid_params(format!("Unsupported type for {}: {}", c.name, c.col_type), Some(serde_json::json!({ "allowed": COLUMN_TYPES }))));
+            }
+            let mut def = format!("{} {}", c.name, ty);
+            if c.primary_key { def.push_str(" PRIMARY KEY"); }
+            if c.not_null { def.push_str(" NOT NULL"); }
+            if c.unique { def.push_str(" UNIQUE"); }
+            if let Some(v) = &c.default {
+                let lit = match v {
+                    Value::Null => "NULL".to_string(),
+                    Value::Bool(b) => (if *b { "1" } else { "0" }).to_string(),
+                    Value::Number(n) => n.to_string(),
+                    Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+                    _ => return Err(ErrorData::invalid_params(format!("Default for {} must be a scalar", c.name), None)),
+                };
+                def.push_str(&format!(" DEFAULT {}", lit));
+            }
+            defs.push(def);
         }
-        let mut sql = format!("UPDATE {} SET {}", input.table, frags.join(", "));
-        if let Some(w) = &input.r#where { sql.push_str(" WHERE "); sql.push_str(w); }
-        let mut q = sqlx::query(&sql);
-        for v in vals { q = bind_value(q, v).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
-        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
-        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "affected_row_count": res.rows_affected() }))
+        let ddl = format!("CREATE TABLE {}{} ({})", if input.if_not_exists { "IF NOT EXISTS " } else { "" }, input.name, defs.join(", "));
+        if input.dry_run { return dry_run_result(&ddl, 0); }
+        sqlx::query(&ddl).execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "ddl": ddl }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "Delete rows; returns affected_row_count")]
-    pub async fn sqlite_delete(&self, params: Parameters<DeleteInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Drop a table; requires confirm: true (unless dry_run) and is subject to the table allow/deny lists. Returns { ok }")]
+    pub async fn sqlite_drop_table(&self, params: Parameters<DropTableInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
         let state = &self.state;
-        if !is_valid_ident(&state.ident_re, &input.table) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
-        let mut sql = format!("DELETE FROM {}", input.table);
-        if let Some(w) = &input.r#where { sql.push_str(" WHERE "); sql.push_str(w); }
-        let mut q = sqlx::query(&sql);
-        if let Some(params) = input.params { for p in params { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; } }
-        let res = q.execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "affected_row_count": res.rows_affected() }))
+        if !is_valid_ident(&state.ident_re, &input.name) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+        check_table_access(state, &input.name)?;
+        let sql = format!("DROP TABLE {}{}", if input.if_exists.unwrap_or(false) { "IF EXISTS " } else { "" }, input.name);
+        if input.dry_run { return dry_run_result(&sql, 0); }
+        if !input.confirm { return Err(ErrorData::invalid_params("Dropping a table requires confirm: true".to_string(), None)); }
+        sqlx::query(&sql).execute(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "ok": true }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    // ---- MCP management tools ----
-    #[tool(description = "Register an MCP server UUID in active_mcp_servers (idempotent)")]
-    pub async fn mcp_register_server(&self, params: Parameters<McpRegisterInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Export the database to a new SQLCipher-encrypted file (requires the sqlcipher build feature); returns { ok, destination_path }")]
+    pub async fn sqlite_export_encrypted(&self, params: Parameters<ExportEncryptedInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        #[cfg(not(feature = "sqlcipher"))]
+        {
+            let _ = params;
+            Err(ErrorData::invalid_params("Encryption support is unavailable: server was built without the sqlcipher feature".to_string(), None))
+        }
+        #[cfg(feature = "sqlcipher")]
+        {
+            let input = params.0;
+            // The exported copy would hold masked columns in clear, readable by whoever chose the passphrase
+            if !self.state.masked_columns.is_empty() {
+                return Err(ErrorData::invalid_params("Export is unavailable while MASKED_COLUMNS is set".to_string(), None));
+            }
+            if input.passphrase.is_empty() { return Err(ErrorData::invalid_params("Passphrase must not be empty".to_string(), None)); }
+            if std::path::Path::new(&input.destination_path).exists() {
+                return Err(ErrorData::invalid_params(format!("Destination already exists: {}", input.destination_path), None));
+            }
+            // ATTACH is per-connection, so keep one connection for the whole export
+            let mut conn = self.state.pool.acquire().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            sqlx::query("ATTACH DATABASE ?1 AS encrypted_export KEY ?2")
+                .bind(&input.destination_path)
+                .bind(&input.passphrase)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            let res = sqlx::query("SELECT sqlcipher_export('encrypted_export')").execute(&mut *conn).await;
+            let _ = sqlx::query("DETACH DATABASE encrypted_export").execute(&mut *conn).await;
+            res.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            let content = Content::json(serde_json::json!({ "ok": true, "destination_path": input.destination_path }))
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            Ok(CallToolResult::success(vec![content]))
+        }
+    }
+
+    #[tool(description = "Set PRAGMA user_version (schema version tracking); returns { version }")]
+    pub async fn sqlite_set_user_version(&self, params: Parameters<SetUserVersionInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
-        let sql = "INSERT OR IGNORE INTO active_mcp_servers (mcp_server_uuid) VALUES (?1)";
-        let res = sqlx::query(sql)
-            .bind(input.mcp_server_uuid)
-            .execute(&self.state.pool)
+        if input.version < 0 { return Err(ErrorData::invalid_params("version must be non-negative".to_string(), None)); }
+        // PRAGMA arguments can't be bound; version is a validated integer
+        let sql = format!("PRAGMA user_version = {}", input.version);
+        if input.dry_run { return dry_run_result(&sql, 0); }
+        sqlx::query(&sql).execute(&self.state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "version": input.version }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Get PRAGMA user_version; returns { version }")]
+    pub async fn sqlite_get_user_version(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let version: i32 = sqlx::query_scalar("PRAGMA user_version")
+            .fetch_one(&self.state.pool)
             .await
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+        let content = Content::json(serde_json::json!({ "version": version }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "Unregister an MCP server UUID from active_mcp_servers")]
-    pub async fn mcp_unregister_server(&self, params: Parameters<McpUnregisterInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Set PRAGMA application_id, the file-type marker checked against WARP_SQLITE_EXPECTED_APPLICATION_ID at startup; \
+                          returns { application_id }")]
+    pub async fn sqlite_set_application_id(&self, params: Parameters<SetApplicationIdInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
-        let sql = "DELETE FROM active_mcp_servers WHERE mcp_server_uuid = ?1";
-        let res = sqlx::query(sql)
-            .bind(input.mcp_server_uuid)
-            .execute(&self.state.pool)
+        // PRAGMA arguments can't be bound; application_id is a validated integer
+        let sql = format!("PRAGMA application_id = {}", input.application_id);
+        if input.dry_run { return dry_run_result(&sql, 0); }
+        sqlx::query(&sql).execute(&self.state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "application_id": input.application_id }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Get PRAGMA application_id; returns { application_id }")]
+    pub async fn sqlite_get_application_id(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let application_id: i32 = sqlx::query_scalar("PRAGMA application_id")
+            .fetch_one(&self.state.pool)
             .await
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+        let content = Content::json(serde_json::json!({ "application_id": application_id }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "Set environment variables JSON for an MCP server UUID (upsert)")]
-    pub async fn mcp_set_env(&self, params: Parameters<McpSetEnvInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    // ---- Diagnostic tools ----
+    #[tool(description = "Health check: SELECT 1 through the pool, failing with an error if the database is unreachable; returns { ok, latency_ms, latency_us, pool_idle }")]
+    pub async fn sqlite_ping(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let pool = &self.state.pool;
+        let started = Instant::now();
+        sqlx::query("SELECT 1").execute(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let elapsed = started.elapsed();
+        // latency_ms is what ping has always reported; latency_us resolves sub-millisecond round trips
+        let content = Content::json(serde_json::json!({
+            "ok": true,
+            "latency_ms": elapsed.as_millis() as u64,
+            "latency_us": elapsed.as_micros() as u64,
+            "pool_idle": pool.num_idle() as u32,
+        }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Alias of sqlite_ping; returns { ok, latency_ms, latency_us, pool_idle }")]
+    pub async fn ping(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        self.sqlite_ping().await
+    }
+
+    #[tool(description = "Interrupt an in-flight select by the query_id it was started with, or the most recent one; \
+                          the select fails with an interrupted error; returns { cancelled, query_id }")]
+    pub async fn sqlite_cancel(&self, params: Parameters<CancelInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
-        let env_text = serde_json::to_string(&input.env).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?;
-        let sql = "INSERT INTO mcp_environment_variables (mcp_server_uuid, environment_variables) VALUES (?1, ?2) \
-                   ON CONFLICT(mcp_server_uuid) DO UPDATE SET environment_variables=excluded.environment_variables";
-        let res = sqlx::query(sql)
-            .bind(input.mcp_server_uuid)
-            .bind(env_text)
-            .execute(&self.state.pool)
+        let queries = self.state.active_queries.lock().unwrap();
+        let target = match &input.query_id {
+            Some(id) => queries.iter().rev().find(|q| &q.id == id),
+            None => queries.last(),
+        };
+        let cancelled = target.map(|q| q.id.clone());
+        if let Some(q) = target {
+            // The handle stays valid while registered: the guard is dropped before the connection returns to the pool,
+            // and sqlite3_interrupt is safe to call from another thread
+            unsafe { libsqlite3_sys::sqlite3_interrupt(q.handle as *mut libsqlite3_sys::sqlite3) };
+        }
+        drop(queries);
+        let content = Content::json(serde_json::json!({ "cancelled": cancelled.is_some(), "query_id": cancelled }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "SHA-256 over every row (or the listed columns) as canonical JSON with sorted keys, in rowid/primary key order; \
+                          equal checksums mean unchanged data. Capped at MAX_RESULT_ROWS; returns { checksum, row_count, truncated }")]
+    pub async fn sqlite_compute_checksum(&self, params: Parameters<ComputeChecksumInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        use sha2::{Digest, Sha256};
+        let input = params.0;
+        let state = &self.state;
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        let cols = match input.columns.as_ref().filter(|l| !l.is_empty()) {
+            Some(list) => {
+                for c in list { if !is_valid_ident(&state.ident_re, c) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); } }
+                list.join(", ")
+            }
+            None => "*".to_string(),
+        };
+        let max_rows = state.row_limit("sqlite_compute_checksum", usize::MAX);
+        let mut sql = format!("SELECT {} FROM {}", cols, input.table);
+        if let Some(ob) = stable_order_by(&state.pool, &input.table).await? { sql.push_str(" ORDER BY "); sql.push_str(&ob); }
+        // One extra row tells us whether the cap cut the table short
+        sql.push_str(&format!(" LIMIT {}", max_rows.saturating_add(1).min(i64::MAX as usize)));
+        let rows = sqlx::query(&sql).fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let truncated = rows.len() > max_rows;
+        let mut hasher = Sha256::new();
+        for r in rows.iter().take(max_rows) {
+            // Masked columns are left out, or the checksum would confirm guesses at their values
+            let sorted = row_to_json(r, &[]).into_iter()
+                .filter(|(c, _)| !state.is_masked(&table, c))
+                .collect::<std::collections::BTreeMap<_, _>>();
+            hasher.update(serde_json::to_string(&sorted).map_err(|e| ErrorData::internal_error(e.to_string(), None))?);
+            hasher.update(b"\n");
+        }
+        let content = Content::json(serde_json::json!({
+            "checksum": format!("{:x}", hasher.finalize()),
+            "row_count": rows.len().min(max_rows),
+            "truncated": truncated,
+        })).map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Foreign keys declared on a table (PRAGMA foreign_key_list); to_column is null when the key references \
+                          the parent's primary key implicitly; returns { foreign_keys: [{ from_column, to_table, to_column, on_update, on_delete }] }")]
+    pub async fn sqlite_foreign_keys(&self, params: Parameters<ForeignKeysInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let (schema, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        let rows: Vec<(String, String, Option<String>, String, String)> = sqlx::query_as(
+            "SELECT \"from\", \"table\", \"to\", on_update, on_delete FROM pragma_foreign_key_list(?1, ?2) ORDER BY id, seq")
+            .bind(&table)
+            .bind(&schema)
+            .fetch_all(&state.pool)
             .await
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+        let foreign_keys = rows.into_iter()
+            .map(|(from, table, to, on_update, on_delete)| serde_json::json!({
+                "from_column": from, "to_table": table, "to_column": to, "on_update": on_update, "on_delete": on_delete,
+            }))
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "foreign_keys": foreign_keys }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "Get environment variables JSON for an MCP server UUID")]
-    pub async fn mcp_get_env(&self, params: Parameters<McpGetEnvInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Report foreign key violations (works even with foreign_keys off); returns { violations, violation_count, ok }")]
+    pub async fn sqlite_foreign_key_check(&self, params: Parameters<ForeignKeyCheckInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
-        let sql = "SELECT environment_variables FROM mcp_environment_variables WHERE mcp_server_uuid = ?1";
-        let row = sqlx::query(sql)
-            .bind(input.mcp_server_uuid)
-            .fetch_optional(&self.state.pool)
+        let state = &self.state;
+        let sql = match &input.table {
+            Some(t) => {
+                if !is_valid_ident(&state.ident_re, t) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+                format!("PRAGMA foreign_key_check({})", t)
+            }
+            None => "PRAGMA foreign_key_check".to_string(),
+        };
+        let rows = sqlx::query(&sql).fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let mut violations = Vec::new();
+        for r in rows {
+            let table: String = r.try_get(0).unwrap_or_default();
+            let rowid: Option<i64> = r.try_get(1).unwrap_or_default();
+            let parent: String = r.try_get(2).unwrap_or_default();
+            let fkid: i64 = r.try_get(3).unwrap_or_default();
+            violations.push(serde_json::json!({"table": table, "rowid": rowid, "parent": parent, "fkid": fkid}));
+        }
+        let count = violations.len();
+        let content = Content::json(serde_json::json!({ "violations": violations, "violation_count": count, "ok": count == 0 }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Report journal_mode, WAL size and frame counts after running a checkpoint (mode PASSIVE by default; \
+                          FULL, RESTART or TRUNCATE to force it); returns { journal_mode, wal_autocheckpoint, checkpoint_mode, busy, \
+                          wal_size_bytes, log_frames, checkpointed_frames, needs_checkpoint }")]
+    pub async fn sqlite_wal_info(&self, params: Parameters<WalInfoInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let mode = input.checkpoint_mode.unwrap_or_else(|| "PASSIVE".to_string()).to_ascii_uppercase();
+        if !["PASSIVE", "FULL", "RESTART", "TRUNCATE"].contains(&mode.as_str()) {
+            return Err(ErrorData::invalid_params(format!("Invalid checkpoint_mode: {}", mode), None));
+        }
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&state.pool)
             .await
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let val = if let Some(r) = row {
-            let s: String = r.try_get(0).unwrap_or_default();
-            serde_json::from_str::<Value>(&s).unwrap_or(Value::Null)
-        } else { Value::Null };
-        let content = Content::json(serde_json::json!({ "env": val }))
+        let wal_autocheckpoint: i64 = sqlx::query_scalar("PRAGMA wal_autocheckpoint")
+            .fetch_one(&state.pool)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let row = sqlx::query(&format!("PRAGMA wal_checkpoint({})", mode))
+            .fetch_one(&state.pool)
+            .await
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let busy: i32 = row.try_get(0).unwrap_or_default();
+        let log_frames: i32 = row.try_get(1).unwrap_or_default();
+        let checkpointed_frames: i32 = row.try_get(2).unwrap_or_default();
+        let wal_size_bytes = main_db_file(&state.pool).await?
+            .and_then(|path| std::fs::metadata(format!("{}-wal", path)).ok())
+            .map(|m| m.len());
+        let content = Content::json(serde_json::json!({
+            "journal_mode": journal_mode,
+            "wal_autocheckpoint": wal_autocheckpoint,
+            "checkpoint_mode": mode,
+            "busy": busy != 0,
+            "wal_size_bytes": wal_size_bytes,
+            "log_frames": log_frames,
+            "checkpointed_frames": checkpointed_frames,
+            "needs_checkpoint": log_frames > checkpointed_frames,
+        })).map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    // ---- Notebook tools ----
-    #[tool(description = "Create a notebook with title and body; returns { id }")]
-    pub async fn notebook_create(&self, params: Parameters<NotebookCreateInput>) -> std::result::Result<CallToolResult, ErrorData> {
+    #[tool(description = "Dump the schema as one replayable DDL script (tables, then indexes, views, triggers); \
+                          with_drops prefixes each object with DROP ... IF EXISTS; returns { sql }")]
+    pub async fn sqlite_dump_schema(&self, params: Parameters<DumpSchemaInput>) -> std::result::Result<CallToolResult, ErrorData> {
         let input = params.0;
-        let title = input.title.unwrap_or_else(|| "".to_string());
-        let sql = "INSERT INTO notebooks (title, data) VALUES (?1, ?2)";
-        let res = sqlx::query(sql)
-            .bind(title)
-            .bind(input.body)
-            .execute(&self.state.pool)
-            .await
+        let sql = "SELECT type, name, sql FROM sqlite_master \
+                   WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' \
+                   ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'index' THEN 1 WHEN 'view' THEN 2 ELSE 3 END, rowid";
+        let rows = sqlx::query(sql).fetch_all(&self.state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let mut script = String::new();
+        for r in rows {
+            let kind: String = r.try_get("type").unwrap_or_default();
+            let name: String = r.try_get("name").unwrap_or_default();
+            let ddl: String = r.try_get("sql").unwrap_or_default();
+            if input.with_drops.unwrap_or(false) {
+                script.push_str(&format!("DROP {} IF EXISTS \"{}\";\n", kind.to_uppercase(), name.replace('"', "\"\"")));
+            }
+            script.push_str(&ddl);
+            script.push_str(";\n");
+        }
+        let content = Content::json(serde_json::json!({ "sql": script }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "id": res.last_insert_rowid() }))
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Dump table contents as INSERT statements (all user tables by default), capped at the result row limit; \
+                          returns { sql, rows_dumped, truncated }")]
+    pub async fn sqlite_dump_data(&self, params: Parameters<DumpDataInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        let tables = match input.tables {
+            Some(list) => {
+                for t in &list {
+                    if !is_valid_ident(&state.ident_re, t) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+                    check_table_access(state, t)?;
+                }
+                list
+            }
+            None => sqlx::query_scalar::<_, String>("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+                .fetch_all(&state.pool)
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+                .into_iter()
+                .filter(|t| check_table_access(state, t).is_ok())
+                .collect(),
+        };
+        let mut remaining = state.row_limit("sqlite_dump_data", usize::MAX);
+        let mut script = String::new();
+        let mut rows_dumped = 0usize;
+        let mut truncated = false;
+        for table in &tables {
+            // Fetch one extra row to detect truncation
+            let sql = format!("SELECT * FROM \"{}\" LIMIT {}", table.replace('"', "\"\""), remaining.saturating_add(1));
+            let rows = sqlx::query(&sql).fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            if rows.len() > remaining { truncated = true; }
+            for row in rows.iter().take(remaining) {
+                let cols = row.columns().iter().map(|c| format!("\"{}\"", c.name().replace('"', "\"\""))).collect::<Vec<_>>();
+                let vals = (0..row.len())
+                    .map(|i| if state.is_masked(table, row.column(i).name()) { "'***'".to_string() } else { sql_literal(row, i) })
+                    .collect::<Vec<_>>();
+                script.push_str(&format!("INSERT INTO \"{}\" ({}) VALUES ({});\n", table.replace('"', "\"\""), cols.join(", "), vals.join(", ")));
+            }
+            let dumped = rows.len().min(remaining);
+            rows_dumped += dumped;
+            remaining -= dumped;
+            if truncated { break; }
+        }
+        let content = Content::json(serde_json::json!({ "sql": script, "rows_dumped": rows_dumped, "truncated": truncated }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Report on-disk size of the database file and its -wal/-shm sidecars (null when absent); \
+                          returns { db_size_bytes, wal_size_bytes, shm_size_bytes, total_size_bytes }")]
+    pub async fn sqlite_db_size(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let path = main_db_file(&self.state.pool).await?;
+        let size_of = |suffix: &str| path.as_ref().and_then(|p| std::fs::metadata(format!("{}{}", p, suffix)).ok()).map(|m| m.len());
+        let db = size_of("");
+        let wal = size_of("-wal");
+        let shm = size_of("-shm");
+        let total: u64 = [db, wal, shm].iter().flatten().sum();
+        let content = Content::json(serde_json::json!({
+            "db_size_bytes": db,
+            "wal_size_bytes": wal,
+            "shm_size_bytes": shm,
+            "total_size_bytes": total,
+        })).map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "SQLite library version, source id and compile-time options; returns { version, source_id, compile_options }")]
+    pub async fn sqlite_version_info(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let pool = &self.state.pool;
+        let version: String = sqlx::query_scalar("SELECT sqlite_version()")
+            .fetch_one(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let source_id: String = sqlx::query_scalar("SELECT sqlite_source_id()")
+            .fetch_one(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let compile_options: Vec<String> = sqlx::query_scalar("PRAGMA compile_options")
+            .fetch_all(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let content = Content::json(serde_json::json!({ "version": version, "source_id": source_id, "compile_options": compile_options }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Connection settings from one pooled connection: journal_mode, synchronous, cache_size, mmap_size, \
+                          busy_timeout, foreign_keys, temp_store, wal_autocheckpoint and page_size, as PRAGMA reports them")]
+    pub async fn sqlite_pragmas(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        // Most of these are per-connection, so read them all from the same one
+        let mut conn = self.state.pool.acquire().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&mut *conn).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let mut out = serde_json::Map::new();
+        out.insert("journal_mode".to_string(), Value::from(journal_mode));
+        for pragma in ["synchronous", "cache_size", "mmap_size", "busy_timeout", "foreign_keys", "temp_store", "wal_autocheckpoint", "page_size"] {
+            let v: i64 = sqlx::query_scalar(&format!("PRAGMA {}", pragma))
+                .fetch_one(&mut *conn).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            out.insert(pragma.to_string(), Value::from(v));
+        }
+        let content = Content::json(Value::Object(out))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Databases open on a connection (main, temp and any attached) with their files; \
+                          returns { databases: [{ seq, name, file }] }")]
+    pub async fn sqlite_list_databases(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let rows: Vec<(i64, String, String)> = sqlx::query_as("SELECT seq, name, file FROM pragma_database_list ORDER BY seq")
+            .fetch_all(&self.state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let databases = rows.into_iter()
+            .map(|(seq, name, file)| serde_json::json!({ "seq": seq, "name": name, "file": file }))
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "databases": databases }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Names of the views in the database, subject to the table allow/deny lists; returns { views }")]
+    pub async fn sqlite_list_views(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let state = &self.state;
+        let views = sqlx::query_scalar::<_, String>("SELECT name FROM sqlite_master WHERE type = 'view' ORDER BY name")
+            .fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+            .into_iter()
+            .filter(|v| check_table_access(state, v).is_ok())
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "views": views }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "CREATE VIEW statement of a view; returns { name, sql }")]
+    pub async fn sqlite_get_view_definition(&self, params: Parameters<GetViewDefinitionInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        if !is_valid_ident(&state.ident_re, &input.name) { return Err(ErrorData::invalid_params("Invalid view name".to_string(), None)); }
+        check_table_access(state, &input.name)?;
+        let sql: Option<String> = sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'view' AND name = ?1")
+            .bind(&input.name)
+            .fetch_optional(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let Some(sql) = sql else { return Err(ErrorData::invalid_params(format!("No such view: {}", input.name), None)) };
+        let content = Content::json(serde_json::json!({ "name": input.name, "sql": sql }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Triggers in the database, or on one table, ordered by table and name; returns { triggers: [{ name, table, sql }] }")]
+    pub async fn sqlite_list_triggers(&self, params: Parameters<ListTriggersInput>) -> std::result::Result<CallToolResult, ErrorData> {
+        let input = params.0;
+        let state = &self.state;
+        if let Some(t) = &input.table {
+            if !is_valid_ident(&state.ident_re, t) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+            check_table_access(state, t)?;
+        }
+        let rows: Vec<(String, String, Option<String>)> = sqlx::query_as(
+            "SELECT name, tbl_name, sql FROM sqlite_master WHERE type = 'trigger' AND (?1 IS NULL OR tbl_name = ?1) ORDER BY tbl_name, name")
+            .bind(&input.table)
+            .fetch_all(&state.pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let triggers = rows.into_iter()
+            .filter(|(_, table, _)| check_table_access(state, table).is_ok())
+            .map(|(name, table, sql)| serde_json::json!({ "name": name, "table": table, "sql": sql }))
+            .collect::<Vec<_>>();
+        let content = Content::json(serde_json::json!({ "triggers": triggers }))
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    #[tool(description = "Call counts and latency per tool since server start; returns { stats: { <tool>: { call_count, total_us, avg_us, error_count } } }")]
+    pub async fn sqlite_query_stats(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let stats = self.state.tool_stats.lock().unwrap().iter()
+            .map(|(tool, s)| (tool.clone(), serde_json::json!({
+                "call_count": s.call_count,
+                "total_us": s.total_us,
+                "avg_us": s.total_us.checked_div(s.call_count).unwrap_or(0),
+                "error_count": s.error_count,
+            })))
+            .collect::<serde_json::Map<_, _>>();
+        let content = Content::json(serde_json::json!({ "stats": stats }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "Append delta text to a notebook's body; returns rows_affected")]
-    pub async fn notebook_append(&self, params: Parameters<NotebookAppendInput>) -> std::result::Result<CallToolResult, ErrorData> {
-        let input = params.0;
-        let sql = "UPDATE notebooks SET data = COALESCE(data,'') || ?1 WHERE id = ?2";
-        let res = sqlx::query(sql)
-            .bind(input.delta)
-            .bind(input.id)
-            .execute(&self.state.pool)
-            .await
-            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+    #[tool(description = "Calls that failed with database is locked (SQLITE_BUSY/SQLITE_LOCKED) since server start, by table \
+                          (or tool name when the call has no table); last_busy_ms is a unix timestamp in milliseconds. \
+                          Returns { stats: { <table>: { busy_count, last_busy_ms } } }")]
+    pub async fn sqlite_lock_stats(&self) -> std::result::Result<CallToolResult, ErrorData> {
+        let stats = self.state.busy_stats.lock().unwrap().iter()
+            .map(|(table, s)| (table.clone(), serde_json::json!({ "busy_count": s.busy_count, "last_busy_ms": s.last_busy_ms })))
+            .collect::<serde_json::Map<_, _>>();
+        let content = Content::json(serde_json::json!({ "stats": stats }))
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
+}
 
-    #[tool(description = "Delete a notebook by id; returns rows_affected")]
-    pub async fn notebook_delete(&self, params: Parameters<NotebookDeleteInput>) -> std::result::Result<CallToolResult, ErrorData> {
-        let input = params.0;
-        let sql = "DELETE FROM notebooks WHERE id = ?1";
-        let res = sqlx::query(sql)
-            .bind(input.id)
-            .execute(&self.state.pool)
-            .await
+// Shared query helpers, not exposed as tools
+impl SqliteService {
+    fn register_query(&self, id: Option<String>, handle: usize) -> ActiveQueryGuard {
+        static NEXT_SEQ: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+        let seq = NEXT_SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let queries = self.state.active_queries.clone();
+        queries.lock().unwrap().push(ActiveQuery { id: id.unwrap_or_else(|| seq.to_string()), seq, handle });
+        ActiveQueryGuard { queries, seq }
+    }
+
+    async fn select_null(&self, input: SelectNullInput, is_null: bool, tool: &str) -> std::result::Result<CallToolResult, ErrorData> {
+        if !is_valid_ident(&self.state.ident_re, &input.column) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", input.column), None)); }
+        let cond = format!("{} IS {}NULL", input.column, if is_null { "" } else { "NOT " });
+        let table = input.table.clone();
+        let select = SelectInput {
+            table: input.table,
+            columns: input.columns,
+            r#where: Some(cond.clone()),
+            limit: input.limit,
+            ..Default::default()
+        };
+        let out = self.run_select(select, self.state.row_limit(tool, usize::MAX)).await?;
+        let total_count = count_where(&self.state.pool, &table, Some(&cond), Vec::new()).await
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let content = Content::json(serde_json::json!({ "rows_affected": res.rows_affected() }))
+        let mut body = out.into_json();
+        body["total_count"] = serde_json::json!(total_count);
+        let content = Content::json(body)
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
 
-    #[tool(description = "List notebooks with optional query on title/body; returns id,title,snippet")]
-    pub async fn notebook_list(&self, params: Parameters<NotebookListInput>) -> std::result::Result<CallToolResult, ErrorData> {
-        let input = params.0;
-        let limit = input.limit.unwrap_or(50).clamp(1, 500);
-        let offset = input.offset.unwrap_or(0).max(0);
-        let (sql, bind_query) = if let Some(q) = input.query {
-            ("SELECT id, title, substr(data,1,200) AS snippet FROM notebooks WHERE (title LIKE ?1 OR data LIKE ?2) ORDER BY id DESC LIMIT ?3 OFFSET ?4", Some(q))
-        } else {
-            ("SELECT id, title, substr(data,1,200) AS snippet FROM notebooks ORDER BY id DESC LIMIT ?1 OFFSET ?2", None)
-        };
-        let rows = if let Some(q) = bind_query {
-            let like = format!("%{}%", q);
-            sqlx::query(sql)
-                .bind(&like)
-                .bind(&like)
-                .bind(limit)
-                .bind(offset)
-                .fetch_all(&self.state.pool)
-                .await
-                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
-        } else {
-            sqlx::query(sql)
-                .bind(limit)
-                .bind(offset)
-                .fetch_all(&self.state.pool)
-                .await
-                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+    // Validates a select and renders its SQL with its bind values, with LIMIT one past the returned limit so callers can detect truncation
+    async fn select_sql(&self, input: &SelectInput, max_rows: usize) -> std::result::Result<(String, Vec<Value>, i64), ErrorData> {
+        let state = &self.state;
+        // input.table may be schema-qualified; table is the bare name for access checks and join columns
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        let joins = input.joins.as_deref().unwrap_or_default();
+        let date_cols = input.date_columns.clone().unwrap_or_default();
+        for c in &date_cols {
+            let valid = if joins.is_empty() { is_valid_ident(&state.ident_re, c) } else { split_qualified(&state.ident_re, c).is_some() };
+            if !valid { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); }
+        }
+        let mut join_sql = String::new();
+        let mut tables = vec![table.as_str()];
+        for j in joins {
+            if !is_valid_ident(&state.ident_re, &j.table) { return Err(ErrorData::invalid_params("Invalid table name".to_string(), None)); }
+            check_table_access(state, &j.table)?;
+            let kind = match j.join_type.as_deref().map(|t| t.to_ascii_lowercase()).as_deref() {
+                None | Some("inner") => "INNER",
+                Some("left") => "LEFT",
+                Some(other) => return Err(ErrorData::invalid_params(format!("Invalid join type: {} (expected inner or left)", other), None)),
+            };
+            let left = match split_qualified(&state.ident_re, &j.on_left) {
+                Some((t, _)) if tables.contains(&t) => j.on_left.clone(),
+                None if is_valid_ident(&state.ident_re, &j.on_left) => format!("{}.{}", input.table, j.on_left),
+                _ => return Err(ErrorData::invalid_params(format!("Invalid join column: {}", j.on_left), None)),
+            };
+            if !is_valid_ident(&state.ident_re, &j.on_right) { return Err(ErrorData::invalid_params(format!("Invalid join column: {}", j.on_right), None)); }
+            join_sql.push_str(&format!(" {} JOIN {t} ON {} = {t}.{}", kind, left, j.on_right, t = j.table));
+            tables.push(&j.table);
+        }
+        let cols = if !joins.is_empty() {
+            let list = input.columns.as_ref().filter(|l| !l.is_empty())
+                .ok_or_else(|| ErrorData::invalid_params("columns (as table.column) are required with joins".to_string(), None))?;
+            for c in list {
+                if !split_qualified(&state.ident_re, c).is_some_and(|(t, _)| tables.contains(&t)) {
+                    return Err(ErrorData::invalid_params(format!("Invalid column: {} (expected table.column of a selected table)", c), None));
+                }
+            }
+            list.iter()
+                .map(|c| if date_cols.contains(c) { format!("datetime({c}) AS \"{c}\"") } else { format!("{c} AS \"{c}\"") })
+                .collect::<Vec<_>>()
+                .join(", ")
+        } else if let Some(list) = input.columns.as_ref().filter(|l| !l.is_empty()) {
+            for c in list { if !is_valid_ident(&state.ident_re, c) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); } }
+            list.iter()
+                .map(|c| if date_cols.contains(c) { format!("datetime({c}) AS {c}") } else { c.clone() })
+                .collect::<Vec<_>>()
+                .join(", ")
+        } else if date_cols.is_empty() { "*".to_string() } else {
+            // Later columns win when decoding, so the datetime() aliases override the raw values from *
+            let wrapped = date_cols.iter().map(|c| format!("datetime({c}) AS {c}")).collect::<Vec<_>>();
+            format!("*, {}", wrapped.join(", "))
         };
-        let mut out = Vec::new();
-        for r in rows {
-            let id: i64 = r.try_get("id").unwrap_or_default();
-            let title: String = r.try_get("title").unwrap_or_default();
-            let snippet: String = r.try_get("snippet").unwrap_or_default();
-            out.push(serde_json::json!({"id": id, "title": title, "snippet": snippet}));
+        let mut sql = format!("SELECT {} FROM {}{}", cols, input.table, join_sql);
+        let mut binds = input.params.clone().unwrap_or_default();
+        let mut conditions = Vec::new();
+        if let Some(w) = &input.r#where { conditions.push(strip_sql_comments(w)); }
+        if let Some(t) = &input.tuple_in {
+            if t.columns.is_empty() { return Err(ErrorData::invalid_params("tuple_in.columns must not be empty".to_string(), None)); }
+            for c in &t.columns {
+                let valid = if joins.is_empty() { is_valid_ident(&state.ident_re, c) } else { split_qualified(&state.ident_re, c).is_some_and(|(tbl, _)| tables.contains(&tbl)) };
+                if !valid { return Err(ErrorData::invalid_params(format!("Invalid column: {}", c), None)); }
+            }
+            if let Some((i, row)) = t.rows.iter().enumerate().find(|(_, r)| r.len() != t.columns.len()) {
+                return Err(ErrorData::invalid_params(
+                    format!("tuple_in.rows[{}] has {} values, expected {}", i, row.len(), t.columns.len()),
+                    None,
+                ));
+            }
+            if t.rows.is_empty() { conditions.push("0".to_string()); } else {
+                let tuple = format!("({})", vec!["?"; t.columns.len()].join(", "));
+                conditions.push(format!("({}) IN ({})", t.columns.join(", "), vec![tuple; t.rows.len()].join(", ")));
+                binds.extend(t.rows.iter().flatten().cloned());
+            }
         }
-        let content = Content::json(serde_json::json!({ "items": out }))
-            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        Ok(CallToolResult::success(vec![content]))
+        match conditions.as_slice() {
+            [] => {}
+            [c] => { sql.push_str(" WHERE "); sql.push_str(c); }
+            // Parenthesized so an OR in where can't escape the tuple_in condition
+            _ => { sql.push_str(" WHERE "); sql.push_str(&conditions.iter().map(|c| format!("({})", c)).collect::<Vec<_>>().join(" AND ")); }
+        }
+        if let Some(ob) = &input.order_by { sql.push_str(" ORDER BY "); sql.push_str(&strip_sql_comments(ob)); }
+        else if input.stable.unwrap_or(state.stable_order) {
+            if let Some(ob) = stable_order_by(&state.pool, &input.table).await? {
+                // Qualify with the base table so joined tables' rowids aren't ambiguous
+                let ob = ob.split(", ").map(|c| format!("{}.{}", input.table, c)).collect::<Vec<_>>().join(", ");
+                sql.push_str(" ORDER BY ");
+                sql.push_str(&ob);
+            }
+        }
+        let cap = max_rows.min(i64::MAX as usize) as i64;
+        let limit = input.limit.map(|l| l.clamp(0, cap)).unwrap_or(cap);
+        // One row past the limit tells whether it cut the result short
+        sql.push_str(&format!(" LIMIT {}", limit.saturating_add(1)));
+        if let Some(o) = input.offset { sql.push_str(&format!(" OFFSET {}", o)); }
+        state.check_params(binds.len())?;
+        Ok((sql, binds, limit))
     }
 
-    #[tool(description = "Get a notebook by id; returns full row")]
-    pub async fn notebook_get(&self, params: Parameters<NotebookGetInput>) -> std::result::Result<CallToolResult, ErrorData> {
-        let input = params.0;
-        let sql = "SELECT id, title, data FROM notebooks WHERE id = ?1";
-        let row = sqlx::query(sql)
-            .bind(input.id)
-            .fetch_optional(&self.state.pool)
-            .await
-            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
-        let val = if let Some(r) = row {
-            let id: i64 = r.try_get("id").unwrap_or_default();
-            let title: String = r.try_get("title").unwrap_or_default();
-            let data: String = r.try_get("data").unwrap_or_default();
-            serde_json::json!({"id": id, "title": title, "data": data})
-        } else { serde_json::json!({}) };
-        let content = Content::json(val).map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    // One page of a chunked sqlite_select: up to chunk_rows rows, starting cursor rows past offset in a stable order.
+    // Each call holds only its own page; next_cursor tells the client where to continue
+    async fn select_page(&self, mut input: SelectInput, max_rows: usize) -> std::result::Result<CallToolResult, ErrorData> {
+        let state = &self.state;
+        if input.compress.unwrap_or(false) {
+            return Err(ErrorData::invalid_params("compress can't be combined with chunked".to_string(), None));
+        }
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        check_table_access(state, &table)?;
+        // Without a fixed order, OFFSET could skip or repeat rows between pages
+        if input.order_by.is_none() {
+            if stable_order_by(&state.pool, &input.table).await?.is_none() {
+                return Err(ErrorData::invalid_params("chunked needs order_by for a view or a table without a rowid or primary key".to_string(), None));
+            }
+            input.stable = Some(true);
+        }
+        let done = match input.cursor.as_deref() {
+            None => 0,
+            Some(c) => c.parse::<usize>().map_err(|_| ErrorData::invalid_params(format!("Invalid cursor: {}", c), None))?,
+        };
+        // limit counts across all pages; unbounded when omitted
+        let total = input.limit.map(|l| l.max(0) as usize);
+        let page_rows = input.chunk_rows.unwrap_or(DEFAULT_CHUNK_ROWS).clamp(1, max_rows);
+        let page_rows = total.map_or(page_rows, |t| page_rows.min(t.saturating_sub(done)));
+        let offset = input.offset.unwrap_or(0).max(0) as usize;
+        input.offset = Some(offset.saturating_add(done).min(i64::MAX as usize) as i64);
+        input.limit = Some(page_rows as i64);
+        let out = self.run_select(input, page_rows).await?;
+        let row_count = out.rows.len();
+        let returned = done + row_count;
+        // More rows exist: another page if the overall limit allows, else the result was truncated
+        let next_cursor = (out.truncated && total.is_none_or(|t| returned < t)).then(|| returned.to_string());
+        let content = Content::json(serde_json::json!({
+            "rows": out.rows,
+            "row_count": row_count,
+            "truncated": out.truncated && next_cursor.is_none(),
+            "limit_applied": total,
+            "next_cursor": next_cursor,
+            "total_row_count": returned,
+        })).map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         Ok(CallToolResult::success(vec![content]))
     }
+
+    // Streams the select's rows, converting each as it arrives rather than holding the decoded rows and the JSON together
+    async fn run_select(&self, input: SelectInput, max_rows: usize) -> std::result::Result<SelectedRows, ErrorData> {
+        let state = &self.state;
+        let (sql, binds, limit) = self.select_sql(&input, max_rows).await?;
+        // input.table may be schema-qualified; table is the bare name for masking and errors
+        let (_, table) = parse_qualified_ident(&state.ident_re, &input.table)?;
+        let joins = input.joins.as_deref().unwrap_or_default();
+        let decimal_cols = input.decimal_columns.clone().unwrap_or_default();
+        let omit_nulls = match input.null_handling.as_deref() {
+            None | Some("include") => false,
+            Some("omit") => true,
+            Some(other) => return Err(ErrorData::invalid_params(format!("Invalid null_handling: {} (expected include or omit)", other), None)),
+        };
+        let mut q = sqlx::query(&sql);
+        for p in binds { q = bind_value(q, p).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+        let mut conn = state.pool.acquire().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let handle = conn.lock_handle().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?.as_raw_handle().as_ptr() as usize;
+        let _guard = self.register_query(input.query_id, handle);
+        let mut stream = q.fetch(&mut *conn);
+        let mut rows = Vec::new();
+        let mut truncated = false;
+        loop {
+            let r = match stream.try_next().await {
+                Ok(Some(r)) => r,
+                Ok(None) => break,
+                Err(e) => return Err(query_error(state, &input.table, e).await),
+            };
+            // The SQL fetches one row past the limit to tell whether it cut the result short
+            if rows.len() as i64 == limit { truncated = true; break; }
+            let mut obj = row_to_json(&r, &decimal_cols);
+            if joins.is_empty() { state.mask_row(&table, &mut obj); }
+            else {
+                for (key, v) in obj.iter_mut() {
+                    if key.split_once('.').is_some_and(|(t, c)| state.is_masked(t, c)) { *v = Value::from("***"); }
+                }
+            }
+            if omit_nulls { obj.retain(|_, v| !v.is_null()); }
+            rows.push(obj);
+        }
+        Ok(SelectedRows { rows, truncated, limit_applied: (limit < i64::MAX).then_some(limit) })
+    }
 }
 
-#[tool_handler]
 impl ServerHandler for SqliteService {
+    async fn call_tool(&self, request: CallToolRequestParam, context: RequestContext<RoleServer>) -> std::result::Result<CallToolResult, ErrorData> {
+        let tool = request.name.to_string();
+        // Counted before the flag is read, so the shutdown drain can't see zero while this call slips in;
+        // the guard takes it back off when the call is refused
+        self.state.in_flight.fetch_add(1, Ordering::SeqCst);
+        let _in_flight = InFlightGuard(&self.state.in_flight);
+        if self.state.shutting_down.load(Ordering::SeqCst) {
+            return Err(ErrorData::internal_error("Server is shutting down".to_string(), None));
+        }
+        if self.state.read_only && WRITE_TOOLS.contains(&tool.as_str()) {
+            return Err(ErrorData::invalid_request(format!("{} is unavailable: the server is read-only (READ_ONLY)", tool), None));
+        }
+        let started = Instant::now();
+        if let Some(limit) = self.state.rate_limits.get(&tool).copied() {
+            let mut buckets = self.state.rate_buckets.lock().unwrap();
+            let bucket = buckets.entry(tool.clone())
+                .or_insert_with(|| TokenBucket { tokens: limit.burst.max(1) as f64, refilled_at: started });
+            if let Err(wait) = bucket.take(limit) {
+                let retry_after_ms = u64::try_from(wait.as_millis()).unwrap_or(u64::MAX);
+                return Err(ErrorData::internal_error(
+                    format!("Rate limit exceeded for tool {}", tool),
+                    Some(serde_json::json!({ "retry_after_ms": retry_after_ms })),
+                ));
+            }
+        }
+        let _permit = if UNLIMITED_TOOLS.contains(&tool.as_str()) { None } else {
+            match tokio::time::timeout(self.state.semaphore_timeout, self.state.query_permits.clone().acquire_owned()).await {
+                Ok(Ok(permit)) => Some(permit),
+                _ => return Err(ErrorData::internal_error("Server busy".to_string(), None)),
+            }
+        };
+        let busy_key = request.arguments.as_ref().and_then(|a| a.get("table")).and_then(Value::as_str).unwrap_or(&tool).to_string();
+        let mut res = self.tool_router.call(ToolCallContext::new(self, request, context)).await;
+        if let Err(e) = &res {
+            if is_busy_error(&e.message) {
+                let mut busy = self.state.busy_stats.lock().unwrap();
+                let entry = busy.entry(busy_key).or_default();
+                entry.busy_count += 1;
+                entry.last_busy_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+            }
+        }
+        // Tools declare an outputSchema, so mirror the JSON text result as structuredContent
+        if let Ok(r) = &mut res {
+            if r.structured_content.is_none() && r.is_error != Some(true) && r.content.len() == 1 {
+                // A compressed envelope is mirrored as is; its fields are part of the schema of tools taking compress
+                r.structured_content = r.content.first().and_then(|c| c.as_text())
+                    .and_then(|t| serde_json::from_str::<Value>(&t.text).ok());
+            }
+        }
+        // Unknown names are rejected by the router; don't let them grow the map
+        if !self.tool_router.has_route(&tool) { return res; }
+        let mut stats = self.state.tool_stats.lock().unwrap();
+        let entry = stats.entry(tool).or_default();
+        entry.call_count += 1;
+        entry.total_us += started.elapsed().as_micros() as u64;
+        if res.as_ref().map_or(true, |r| r.is_error == Some(true)) { entry.error_count += 1; }
+        res
+    }
+
+    async fn list_tools(&self, _request: Option<PaginatedRequestParam>, _context: RequestContext<RoleServer>) -> std::result::Result<ListToolsResult, ErrorData> {
+        Ok(ListToolsResult { tools: self.tool_router.list_all(), next_cursor: None })
+    }
+
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             protocol_version: ProtocolVersion::V_2024_11_05,
@@ -421,6 +3891,12 @@ impl ServerHandler for SqliteService {
     }
 }
 
+// SQLITE_BUSY (5) and SQLITE_LOCKED (6), as sqlx formats them
+fn is_busy_error(message: &str) -> bool {
+    message.contains("database is locked") || message.contains("database table is locked")
+        || message.contains("(code: 5)") || message.contains("(code: 6)")
+}
+
 fn bind_value<'q>(mut q: sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>, v: Value)
     -> Result<sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>, anyhow::Error>
 {
@@ -440,3 +3916,666 @@ fn bind_value<'q>(mut q: sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArgu
     Ok(q)
 }
 
+// Renders a cell as a SQL literal: strings quoted, blobs as X'..', NULL for null
+fn sql_literal(row: &SqliteRow, i: usize) -> String {
+    let Ok(raw) = row.try_get_raw(i) else { return "NULL".to_string() };
+    if raw.is_null() { return "NULL".to_string(); }
+    match raw.type_info().name() {
+        "INTEGER" => row.try_get::<i64, _>(i).map(|v| v.to_string()).unwrap_or_else(|_| "NULL".to_string()),
+        "REAL" => match row.try_get::<f64, _>(i) {
+            Ok(f) if f.is_infinite() => if f > 0.0 { "9e999".to_string() } else { "-9e999".to_string() },
+            Ok(f) => format!("{:?}", f),
+            Err(_) => "NULL".to_string(),
+        },
+        "BLOB" => {
+            let bytes = row.try_get::<Vec<u8>, _>(i).unwrap_or_default();
+            format!("X'{}'", bytes.iter().map(|b| format!("{:02X}", b)).collect::<String>())
+        }
+        _ => {
+            let text = row.try_get_unchecked::<String, _>(i).unwrap_or_default();
+            format!("'{}'", text.replace('\'', "''"))
+        }
+    }
+}
+
+// "no such table" becomes invalid_params with data { kind, table, suggestions } naming the closest accessible tables;
+// any other query error stays an internal_error. For schema.table the suggestions come from that schema, qualified the same way
+async fn query_error(state: &AppState, table: &str, e: sqlx::Error) -> ErrorData {
+    let msg = e.to_string();
+    if !msg.contains(&format!("no such table: {}", table)) { return ErrorData::internal_error(msg, None); }
+    let (schema, bare) = match table.split_once('.') { Some((s, t)) => (Some(s), t), None => (None, table) };
+    let sql = format!("SELECT name FROM {}.sqlite_master WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%'", schema.unwrap_or("main"));
+    let names: Vec<String> = sqlx::query_scalar(&sql).fetch_all(&state.pool).await.unwrap_or_default();
+    let wanted = bare.to_lowercase();
+    let mut scored = names.into_iter()
+        .filter(|n| check_table_access(state, n).is_ok())
+        .map(|n| (edit_distance(&n.to_lowercase(), &wanted), n))
+        .filter(|(d, _)| *d <= 3)
+        .collect::<Vec<_>>();
+    scored.sort();
+    let suggestions = scored.into_iter().take(3)
+        .map(|(_, n)| match schema { Some(s) => format!("{}.{}", s, n), None => n })
+        .collect::<Vec<_>>();
+    ErrorData::invalid_params(
+        format!("No such table: {}", table),
+        Some(serde_json::json!({ "kind": "no_such_table", "table": table, "suggestions": suggestions })),
+    )
+}
+
+// Levenshtein distance over chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            cur[j + 1] = (prev[j] + usize::from(ca != *cb)).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// Replaces -- and /* */ comments in a caller-supplied fragment with a space, leaving quoted text alone. A trailing --
+// in a where clause would otherwise comment out the LIMIT or conditions appended after it. Block comments are
+// stripped with nesting, and an unterminated one runs to the end, as SQLite treats it.
+fn strip_sql_comments(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                out.push(c);
+                // A doubled quote is an escaped quote and stays inside the literal
+                while let Some(q) = chars.next() {
+                    out.push(q);
+                    if q == c {
+                        if chars.peek() == Some(&c) { out.push(chars.next().unwrap()); } else { break; }
+                    }
+                }
+            }
+            '[' => {
+                out.push(c);
+                for q in chars.by_ref() { out.push(q); if q == ']' { break; } }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for q in chars.by_ref() { if q == '\n' { break; } }
+                out.push(' ');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('/') if chars.peek() == Some(&'*') => { chars.next(); depth += 1; }
+                        Some('*') if chars.peek() == Some(&'/') => { chars.next(); depth -= 1; }
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// SELECT COUNT(*) FROM table [WHERE ...]; table must already be validated
+async fn count_where(pool: &Pool<Sqlite>, table: &str, r#where: Option<&str>, params: Vec<Value>) -> Result<i64> {
+    let mut sql = format!("SELECT COUNT(*) FROM {}", table);
+    if let Some(w) = r#where { sql.push_str(" WHERE "); sql.push_str(&strip_sql_comments(w)); }
+    let mut q = sqlx::query(&sql);
+    for p in params { q = bind_value(q, p)?; }
+    Ok(q.fetch_one(pool).await?.try_get(0)?)
+}
+
+// Deterministic ordering for a table: rowid, or the primary key of a WITHOUT ROWID table; None for views and keyless tables.
+// table may be schema-qualified (already checked by parse_qualified_ident); it's looked up in that schema's sqlite_master
+async fn stable_order_by(pool: &Pool<Sqlite>, table: &str) -> std::result::Result<Option<String>, ErrorData> {
+    let (schema, table) = table.split_once('.').unwrap_or(("main", table));
+    let sql = format!("SELECT type, sql FROM {}.sqlite_master WHERE name = ?", schema);
+    let def: Option<(String, Option<String>)> = sqlx::query_as(&sql)
+        .bind(table)
+        .fetch_optional(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    let Some((kind, ddl)) = def else { return Ok(None) };
+    if kind != "table" { return Ok(None); }
+    if !ddl.unwrap_or_default().to_uppercase().contains("WITHOUT ROWID") { return Ok(Some("rowid".to_string())); }
+    let pk: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?1, ?2) WHERE pk > 0 ORDER BY pk")
+        .bind(table)
+        .bind(schema)
+        .fetch_all(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(if pk.is_empty() { None } else { Some(pk.join(", ")) })
+}
+
+// Result for write tools called with dry_run: the generated SQL (placeholders only, never values) without executing it
+fn dry_run_result(sql: &str, param_count: usize) -> std::result::Result<CallToolResult, ErrorData> {
+    let content = Content::json(serde_json::json!({ "sql": sql, "param_count": param_count, "dry_run": true }))
+        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(CallToolResult::success(vec![content]))
+}
+
+// Batch transaction opened by begin_batch. Dropped before commit (an error or a cancelled call), it rolls back
+// in the background before the connection returns to the pool.
+struct BatchTx { conn: Option<sqlx::pool::PoolConnection<Sqlite>> }
+impl BatchTx {
+    async fn commit(mut self) -> std::result::Result<(), ErrorData> {
+        let Some(mut conn) = self.conn.take() else { return Ok(()) };
+        if let Err(e) = sqlx::query("COMMIT").execute(&mut *conn).await {
+            let _ = sqlx::query("ROLLBACK").execute(&mut *conn).await;
+            return Err(ErrorData::internal_error(e.to_string(), None));
+        }
+        Ok(())
+    }
+}
+impl std::ops::Deref for BatchTx {
+    type Target = sqlx::SqliteConnection;
+    fn deref(&self) -> &Self::Target { self.conn.as_ref().expect("batch connection taken") }
+}
+impl std::ops::DerefMut for BatchTx {
+    fn deref_mut(&mut self) -> &mut Self::Target { self.conn.as_mut().expect("batch connection taken") }
+}
+impl Drop for BatchTx {
+    fn drop(&mut self) {
+        if let Some(mut conn) = self.conn.take() {
+            tokio::spawn(async move { let _ = sqlx::query("ROLLBACK").execute(&mut *conn).await; });
+        }
+    }
+}
+
+// continue_on_error batches: runs sql once per key (bound after leading) inside a SAVEPOINT; a failing key is rolled back
+// to its savepoint and reported, the rest stay in the batch. Returns the rows changed and one BatchItemResult per key
+async fn run_per_key(tx: &mut BatchTx, sql: &str, leading: &[Value], keys: &[Value]) -> std::result::Result<(u64, Vec<Value>), ErrorData> {
+    let mut total = 0u64;
+    let mut results = Vec::with_capacity(keys.len());
+    for key in keys {
+        let mut q = sqlx::query(sql);
+        for v in leading.iter().chain(std::iter::once(key)) { q = bind_value(q, v.clone()).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+        sqlx::query("SAVEPOINT batch_item").execute(&mut **tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        match q.execute(&mut **tx).await {
+            Ok(res) => {
+                total += res.rows_affected();
+                results.push(serde_json::json!({ "pk_value": key, "ok": true, "rows_affected": res.rows_affected() }));
+            }
+            Err(e) => {
+                sqlx::query("ROLLBACK TO batch_item").execute(&mut **tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+                results.push(serde_json::json!({ "pk_value": key, "ok": false, "error": e.to_string() }));
+            }
+        }
+        sqlx::query("RELEASE batch_item").execute(&mut **tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    }
+    Ok((total, results))
+}
+
+// Opens a batch transaction: "deferred" (default), "immediate" or "exclusive"
+async fn begin_batch(pool: &Pool<Sqlite>, mode: Option<&str>) -> std::result::Result<BatchTx, ErrorData> {
+    let begin = match mode.unwrap_or("deferred") {
+        "deferred" => "BEGIN DEFERRED",
+        "immediate" => "BEGIN IMMEDIATE",
+        "exclusive" => "BEGIN EXCLUSIVE",
+        other => return Err(ErrorData::invalid_params(format!("Invalid mode: {} (expected deferred, immediate or exclusive)", other), None)),
+    };
+    let mut conn = pool.acquire().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    sqlx::query(begin).execute(&mut *conn).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(BatchTx { conn: Some(conn) })
+}
+
+// JSON tool result; with compress and a payload over the threshold, { _compressed: true, encoding: "gzip+base64", data } instead.
+// Only on request: stdio has no content negotiation, so the server never decides to compress on its own
+fn json_result(state: &AppState, value: Value, compress: bool) -> std::result::Result<CallToolResult, ErrorData> {
+    let text = serde_json::to_string(&value).map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    if !compress || text.len() <= state.compress_threshold {
+        return Ok(CallToolResult::success(vec![Content::text(text)]));
+    }
+    use std::io::Write;
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let gzipped = gz.write_all(text.as_bytes()).and_then(|_| gz.finish())
+        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    let content = Content::json(serde_json::json!({ "_compressed": true, "encoding": COMPRESSED_ENCODING, "data": B64.encode(gzipped) }))
+        .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(CallToolResult::success(vec![content]))
+}
+
+// DELETE FROM table<filter>, returning the key column of each deleted row
+async fn delete_returning(conn: &mut sqlx::SqliteConnection, table: &str, key: &str, filter: &str, binds: Vec<Value>)
+    -> std::result::Result<Vec<Value>, ErrorData>
+{
+    let sql = format!("DELETE FROM {}{} RETURNING {} AS id", table, filter, key);
+    let mut q = sqlx::query(&sql);
+    for v in binds { q = bind_value(q, v).map_err(|e| ErrorData::invalid_params(e.to_string(), None))?; }
+    let rows = q.fetch_all(&mut *conn).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(rows.iter().map(|r| row_to_json(r, &[]).remove("id").unwrap_or(Value::Null)).collect())
+}
+
+// Runs a write; with a cap it runs in a transaction and rolls back when rows_affected exceeds it
+async fn execute_capped<'q>(pool: &Pool<Sqlite>, q: sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>, max_affected: Option<i64>)
+    -> std::result::Result<sqlx::sqlite::SqliteQueryResult, ErrorData>
+{
+    let Some(max) = max_affected else {
+        return q.execute(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None));
+    };
+    let mut tx = pool.begin().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    let res = q.execute(&mut *tx).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    let would_affect = res.rows_affected();
+    if would_affect > max.max(0) as u64 {
+        tx.rollback().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        return Err(ErrorData::invalid_params(
+            format!("Operation would affect {} rows, exceeding max_affected {}; rolled back", would_affect, max),
+            Some(serde_json::json!({ "would_affect": would_affect, "max_affected": max })),
+        ));
+    }
+    tx.commit().await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(res)
+}
+
+// Columns in text_columns are read as SQLite's text rendering of the value, keeping NUMERIC precision intact
+fn row_to_json(row: &SqliteRow, text_columns: &[String]) -> serde_json::Map<String, Value> {
+    let mut obj = serde_json::Map::new();
+    for col in row.columns() {
+        let i = col.ordinal();
+        let v = match row.try_get_raw(i) {
+            Ok(r) if r.is_null() => Value::Null,
+            Ok(_) if text_columns.iter().any(|c| c == col.name()) => {
+                row.try_get_unchecked::<String, _>(i).map(Value::from).unwrap_or(Value::Null)
+            }
+            Ok(_) => {
+                if let Ok(v) = row.try_get::<i64, _>(i) { Value::from(v) }
+                else if let Ok(v) = row.try_get::<f64, _>(i) { Value::from(v) }
+                else if let Ok(v) = row.try_get::<String, _>(i) { Value::from(v) }
+                else if let Ok(v) = row.try_get::<Vec<u8>, _>(i) { Value::from(B64.encode(v)) }
+                else { Value::Null }
+            }
+            Err(_) => Value::Null,
+        };
+        obj.insert(col.name().to_string(), v);
+    }
+    obj
+}
+
+// File backing the main database, or None for in-memory/temporary databases
+async fn main_db_file(pool: &Pool<Sqlite>) -> std::result::Result<Option<String>, ErrorData> {
+    let rows = sqlx::query("PRAGMA database_list").fetch_all(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    for r in rows {
+        let name: String = r.try_get(1).unwrap_or_default();
+        if name == "main" {
+            let file: String = r.try_get(2).unwrap_or_default();
+            return Ok(Some(file).filter(|f| !f.is_empty()));
+        }
+    }
+    Ok(None)
+}
+
+// Rejects string values in the listed columns that don't parse as JSON (objects/arrays are serialized by bind_value)
+fn validate_json_columns(json_columns: Option<&[String]>, values: &serde_json::Map<String, Value>) -> std::result::Result<(), ErrorData> {
+    for col in json_columns.unwrap_or_default() {
+        if let Some(Value::String(text)) = values.get(col) {
+            serde_json::from_str::<Value>(text)
+                .map_err(|e| ErrorData::invalid_params(format!("Invalid JSON in column {}: {}", col, e), None))?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_tag(tag: &str) -> std::result::Result<(), ErrorData> {
+    if tag.is_empty() || tag.chars().count() >= 100 {
+        return Err(ErrorData::invalid_params("tag must be non-empty and under 100 characters".to_string(), None));
+    }
+    Ok(())
+}
+
+// The tag join table isn't part of Warp's schema, so the tag tools create it on first use
+async fn ensure_notebook_tags(pool: &Pool<Sqlite>) -> std::result::Result<(), ErrorData> {
+    sqlx::query("CREATE TABLE IF NOT EXISTS notebook_tags (notebook_id INTEGER NOT NULL, tag TEXT NOT NULL, PRIMARY KEY (notebook_id, tag))")
+        .execute(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(())
+}
+
+// Read-only tag tools report no tags instead of creating the table
+async fn notebook_tags_exist(pool: &Pool<Sqlite>) -> std::result::Result<bool, ErrorData> {
+    let found: Option<i64> = sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'notebook_tags'")
+        .fetch_optional(pool).await.map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+    Ok(found.is_some())
+}
+
+// Columns whose placeholders become jsonb(?); errors up front when the SQLite build has no JSONB
+fn jsonb_columns<'a>(state: &AppState, cols: Option<&'a [String]>) -> std::result::Result<&'a [String], ErrorData> {
+    let cols = cols.unwrap_or_default();
+    if !cols.is_empty() && !state.jsonb_supported {
+        return Err(ErrorData::invalid_params("jsonb_columns requires SQLite 3.45 or newer with JSONB support".to_string(), None));
+    }
+    Ok(cols)
+}
+
+// Validates date strings in the listed columns via SQLite's datetime() and rewrites them as YYYY-MM-DD HH:MM:SS
+async fn normalize_dates(state: &AppState, date_columns: Option<&[String]>, values: &mut serde_json::Map<String, Value>) -> std::result::Result<(), ErrorData> {
+    for col in date_columns.unwrap_or_default() {
+        if !is_valid_ident(&state.ident_re, col) { return Err(ErrorData::invalid_params(format!("Invalid column: {}", col), None)); }
+        let Some(v) = values.get_mut(col) else { continue };
+        let normalized = match v {
+            Value::Null => continue,
+            Value::String(s) => sqlx::query_scalar::<_, Option<String>>("SELECT datetime(?1)")
+                .bind(s.as_str())
+                .fetch_one(&state.pool)
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?,
+            _ => None,
+        };
+        match normalized {
+            Some(n) => *v = Value::String(n),
+            None => return Err(ErrorData::invalid_params(format!("Invalid date in column {}: {}", col, v), None)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A service over a fresh file database with every optional setting off; configure adjusts the state
+    async fn test_service(configure: impl FnOnce(&mut AppState)) -> SqliteService {
+        static NEXT_DB: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!("warp-sqlite-mcp-test-{}-{}.sqlite", std::process::id(), NEXT_DB.fetch_add(1, Ordering::SeqCst)));
+        let _ = std::fs::remove_file(&path);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(&format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        ensure_idempotency_table(&pool).await;
+        let mut state = AppState {
+            pool,
+            ident_re: Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap(),
+            allowed_tables: None,
+            denied_tables: DEFAULT_DENIED_TABLES.iter().map(|t| t.to_string()).collect(),
+            request_cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(IDEMPOTENCY_CACHE_SIZE).unwrap()))),
+            max_result_rows: DEFAULT_MAX_RESULT_ROWS,
+            tool_limits: HashMap::new(),
+            masked_columns: HashMap::new(),
+            stable_order: false,
+            max_params: DEFAULT_MAX_PARAMS,
+            jsonb_supported: false,
+            compress_threshold: DEFAULT_COMPRESS_THRESHOLD,
+            active_queries: Arc::new(Mutex::new(Vec::new())),
+            tool_stats: Arc::new(Mutex::new(HashMap::new())),
+            busy_stats: Arc::new(Mutex::new(HashMap::new())),
+            rate_limits: HashMap::new(),
+            rate_buckets: Arc::new(Mutex::new(HashMap::new())),
+            query_permits: Arc::new(tokio::sync::Semaphore::new(MAX_CONNECTIONS as usize - 1)),
+            semaphore_timeout: Duration::from_millis(DEFAULT_SEMAPHORE_TIMEOUT_MS),
+            read_only: false,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        };
+        configure(&mut state);
+        SqliteService { state: Arc::new(state), tool_router: SqliteService::tool_router() }
+    }
+
+    fn args<T: serde::de::DeserializeOwned>(v: Value) -> Parameters<T> {
+        Parameters(serde_json::from_value(v).unwrap())
+    }
+
+    fn body(res: CallToolResult) -> Value {
+        serde_json::from_str(&res.content[0].as_text().unwrap().text).unwrap()
+    }
+
+    async fn exec(svc: &SqliteService, sql: &str) {
+        sqlx::query(sql).execute(&svc.state.pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn denylist_ignores_case() {
+        let svc = test_service(|s| { s.denied_tables.insert("secrets".to_string()); }).await;
+        for table in ["SQLITE_MASTER", "Sqlite_Stat1", "sqlite_schema", "main.SQLite_Master", "SECRETS"] {
+            let err = svc.sqlite_select(args(serde_json::json!({ "table": table }))).await.unwrap_err();
+            assert_eq!(err.message, "Table access denied", "{}", table);
+        }
+    }
+
+    #[tokio::test]
+    async fn chunked_select_pages_with_a_cursor() {
+        let svc = test_service(|_| {}).await;
+        exec(&svc, "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)").await;
+        exec(&svc, "INSERT INTO t (v) VALUES ('a'), ('b'), ('c'), ('d'), ('e')").await;
+        let mut cursor = Value::Null;
+        let mut ids = Vec::new();
+        let mut pages = 0;
+        loop {
+            let page = body(svc.sqlite_select(args(serde_json::json!({ "table": "t", "chunked": true, "chunk_rows": 2, "cursor": cursor }))).await.unwrap());
+            pages += 1;
+            ids.extend(page["rows"].as_array().unwrap().iter().map(|r| r["id"].as_i64().unwrap()));
+            assert_eq!(page["total_row_count"], ids.len());
+            cursor = page["next_cursor"].clone();
+            if cursor.is_null() { assert_eq!(page["truncated"], false); break; }
+        }
+        assert_eq!((pages, ids), (3, vec![1, 2, 3, 4, 5]));
+
+        // limit caps the rows across pages, and truncated reports what it cut off
+        let first = body(svc.sqlite_select(args(serde_json::json!({ "table": "t", "chunked": true, "chunk_rows": 2, "limit": 3 }))).await.unwrap());
+        let last = body(svc.sqlite_select(args(serde_json::json!({ "table": "t", "chunked": true, "chunk_rows": 2, "limit": 3, "cursor": first["next_cursor"] }))).await.unwrap());
+        assert_eq!(last["rows"].as_array().unwrap().len(), 1);
+        assert_eq!((last["next_cursor"].clone(), last["truncated"].clone()), (Value::Null, Value::from(true)));
+    }
+
+    #[tokio::test]
+    async fn dry_run_drop_and_swap_leave_tables_alone() {
+        let svc = test_service(|_| {}).await;
+        exec(&svc, "CREATE TABLE a (id INTEGER)").await;
+        exec(&svc, "CREATE TABLE b (id INTEGER)").await;
+        let drop = body(svc.sqlite_drop_table(args(serde_json::json!({ "name": "a", "dry_run": true }))).await.unwrap());
+        assert_eq!((drop["sql"].as_str().unwrap(), drop["dry_run"].as_bool()), ("DROP TABLE a", Some(true)));
+        let swap = body(svc.sqlite_swap_tables(args(serde_json::json!({ "table_a": "a", "table_b": "b", "dry_run": true }))).await.unwrap());
+        assert_eq!(swap["sql"], "ALTER TABLE a RENAME TO a__swap_tmp;\nALTER TABLE b RENAME TO a;\nALTER TABLE a__swap_tmp RENAME TO b");
+        let tables: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name IN ('a', 'b') ORDER BY name")
+            .fetch_all(&svc.state.pool).await.unwrap();
+        assert_eq!(tables, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn compare_and_set_replays_by_idempotency_key() {
+        let svc = test_service(|_| {}).await;
+        exec(&svc, "CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)").await;
+        exec(&svc, "INSERT INTO t (v) VALUES (1)").await;
+        let call = serde_json::json!({ "table": "t", "set": { "v": 2 }, "expect": { "v": 1 }, "where": "id = 1", "idempotency_key": "k1" });
+        let first = body(svc.sqlite_compare_and_set(args(call.clone())).await.unwrap());
+        // Without the key the retry would see v = 2 and report updated: false
+        let retry = body(svc.sqlite_compare_and_set(args(call)).await.unwrap());
+        assert_eq!((first["updated"].clone(), retry), (Value::from(true), first));
+    }
+
+    #[tokio::test]
+    async fn select_in_checks_access_with_empty_values() {
+        let svc = test_service(|_| {}).await;
+        let err = svc.sqlite_select_in(args(serde_json::json!({ "table": "sqlite_master", "column": "name", "values": [] }))).await.unwrap_err();
+        assert_eq!(err.message, "Table access denied");
+    }
+
+    async fn masked_people(svc: &SqliteService) {
+        exec(svc, "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT, ssn TEXT)").await;
+        exec(svc, "CREATE TABLE copy (id INTEGER PRIMARY KEY, name TEXT, ssn TEXT)").await;
+        exec(svc, "INSERT INTO people (name, ssn) VALUES ('ann', '111'), ('bob', '222')").await;
+    }
+
+    fn mask_ssn(s: &mut AppState) {
+        s.masked_columns.insert("people".to_string(), vec!["ssn".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn masks_ignore_case() {
+        let svc = test_service(mask_ssn).await;
+        masked_people(&svc).await;
+        for (table, column) in [("people", "SSN"), ("PEOPLE", "ssn"), ("main.People", "Ssn")] {
+            let out = body(svc.sqlite_select(args(serde_json::json!({ "table": table, "columns": ["id", column], "limit": 1 }))).await.unwrap());
+            let row = out["rows"][0].as_object().unwrap();
+            let (_, ssn) = row.iter().find(|(k, _)| k.eq_ignore_ascii_case("ssn")).unwrap();
+            assert_eq!(ssn, "***", "{}.{}", table, column);
+        }
+    }
+
+    #[tokio::test]
+    async fn masked_columns_cant_be_copied_out() {
+        let svc = test_service(mask_ssn).await;
+        masked_people(&svc).await;
+        let calls = [
+            svc.sqlite_copy_rows(args(serde_json::json!({ "source_table": "People", "dest_table": "copy", "column_map": { "SSN": "name" } }))).await,
+            svc.sqlite_insert_from_select(args(serde_json::json!({ "source_table": "people", "dest_table": "copy" }))).await,
+            svc.sqlite_insert_from_select(args(serde_json::json!({ "source_table": "people", "dest_table": "copy", "columns": ["id", "SSN"] }))).await,
+            svc.sqlite_merge_tables(args(serde_json::json!({ "source_table": "people", "dest_table": "copy" }))).await,
+            svc.sqlite_swap_tables(args(serde_json::json!({ "table_a": "copy", "table_b": "PEOPLE" }))).await,
+            svc.sqlite_history_table(args(serde_json::json!({ "table": "people" }))).await,
+        ];
+        for (i, res) in calls.into_iter().enumerate() {
+            assert!(res.unwrap_err().message.starts_with("Column is masked"), "call {}", i);
+        }
+        let copied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM copy").fetch_one(&svc.state.pool).await.unwrap();
+        assert_eq!(copied, 0);
+        // Unmasked columns still copy
+        let ok = body(svc.sqlite_copy_rows(args(serde_json::json!({ "source_table": "people", "dest_table": "copy", "column_map": { "name": "name" } }))).await.unwrap());
+        assert_eq!(ok["rows_copied"], 2);
+    }
+
+    #[tokio::test]
+    async fn checksum_ignores_masked_columns() {
+        let svc = test_service(mask_ssn).await;
+        masked_people(&svc).await;
+        let checksum = || async { body(svc.sqlite_compute_checksum(args(serde_json::json!({ "table": "people" }))).await.unwrap())["checksum"].clone() };
+        let before = checksum().await;
+        exec(&svc, "UPDATE people SET ssn = '999' WHERE id = 1").await;
+        assert_eq!(checksum().await, before);
+        exec(&svc, "UPDATE people SET name = 'amy' WHERE id = 1").await;
+        assert_ne!(checksum().await, before);
+    }
+
+    #[tokio::test]
+    async fn truncate_protects_tables_in_any_case() {
+        let svc = test_service(|_| {}).await;
+        exec(&svc, "CREATE TABLE notebooks (id INTEGER PRIMARY KEY, title TEXT, data TEXT)").await;
+        exec(&svc, "INSERT INTO notebooks (title) VALUES ('keep')").await;
+        for table in ["notebooks", "NOTEBOOKS", "main.Notebooks"] {
+            let err = svc.sqlite_truncate(args(serde_json::json!({ "table": table }))).await.unwrap_err();
+            assert!(err.message.contains("protected table"), "{}", table);
+        }
+        let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM notebooks").fetch_one(&svc.state.pool).await.unwrap();
+        assert_eq!(left, 1);
+    }
+
+    #[tokio::test]
+    async fn delete_rolls_back_past_max_affected() {
+        let svc = test_service(|_| {}).await;
+        exec(&svc, "CREATE TABLE t (id INTEGER PRIMARY KEY)").await;
+        exec(&svc, "INSERT INTO t (id) VALUES (1), (2), (3)").await;
+        assert!(svc.sqlite_delete(args(serde_json::json!({ "table": "t", "where": "id > ?", "params": [1], "max_affected": 1 }))).await.is_err());
+        let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM t").fetch_one(&svc.state.pool).await.unwrap();
+        assert_eq!(left, 3);
+        let out = body(svc.sqlite_delete(args(serde_json::json!({ "table": "t", "where": "id > ?", "params": [1], "max_affected": 2 }))).await.unwrap());
+        assert_eq!(out["affected_row_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_in_flight_calls() {
+        let svc = test_service(|_| {}).await;
+        let state = svc.state.clone();
+        state.in_flight.fetch_add(1, Ordering::SeqCst);
+        assert!(!drain_in_flight(&state, Duration::from_millis(100)).await);
+        assert!(state.shutting_down.load(Ordering::SeqCst));
+        let finishing = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            finishing.in_flight.fetch_sub(1, Ordering::SeqCst);
+        });
+        assert!(drain_in_flight(&state, Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn continue_on_error_rolls_back_only_the_failing_key() {
+        let svc = test_service(|_| {}).await;
+        exec(&svc, "CREATE TABLE t (id INTEGER PRIMARY KEY, code TEXT UNIQUE)").await;
+        exec(&svc, "INSERT INTO t (id, code) VALUES (1, 'a'), (2, 'b'), (3, 'c')").await;
+        let call = |continue_on_error| serde_json::json!({
+            "table": "t", "pk_column": "id", "pk_values": [1, 2, 3], "set": { "code": "z" }, "continue_on_error": continue_on_error,
+        });
+        // All-or-nothing by default: keys 2 and 3 can't also take 'z', so nothing changes
+        assert!(svc.sqlite_update_where_in(args(call(false))).await.is_err());
+        let out = body(svc.sqlite_update_where_in(args(call(true))).await.unwrap());
+        let oks = out["results"].as_array().unwrap().iter().map(|r| r["ok"].as_bool().unwrap()).collect::<Vec<_>>();
+        assert_eq!((out["rows_affected"].as_u64(), oks), (Some(1), vec![true, false, false]));
+        let codes: Vec<String> = sqlx::query_scalar("SELECT code FROM t ORDER BY id").fetch_all(&svc.state.pool).await.unwrap();
+        assert_eq!(codes, ["z", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn compress_marks_and_round_trips_large_results() {
+        let svc = test_service(|s| { s.compress_threshold = 16; }).await;
+        let value = serde_json::json!({ "rows": ["x".repeat(100)] });
+        assert_eq!(body(json_result(&svc.state, value.clone(), false).unwrap()), value);
+        let out = body(json_result(&svc.state, value.clone(), true).unwrap());
+        assert_eq!((out["_compressed"].clone(), out["encoding"].clone()), (Value::from(true), Value::from(COMPRESSED_ENCODING)));
+        let gzipped = B64.decode(out["data"].as_str().unwrap()).unwrap();
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&gzipped[..]), &mut text).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn temp_schema_is_refused() {
+        let re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+        assert!(parse_qualified_ident(&re, "TEMP.scratch").is_err());
+        assert_eq!(parse_qualified_ident(&re, "main.temp").unwrap(), (Some("main".to_string()), "temp".to_string()));
+    }
+
+    #[tokio::test]
+    async fn notebook_items_are_masked() {
+        let svc = test_service(|s| { s.masked_columns.insert("notebooks".to_string(), vec!["data".to_string()]); }).await;
+        exec(&svc, "CREATE TABLE notebooks (id INTEGER PRIMARY KEY, title TEXT, data TEXT)").await;
+        exec(&svc, "INSERT INTO notebooks (title, data) VALUES ('plan', 'secret body')").await;
+        let out = body(svc.notebook_list_by_ids(args(serde_json::json!({ "ids": [1], "include_body": true }))).await.unwrap());
+        let item = &out["items"][0];
+        assert_eq!((&item["title"], &item["snippet"], &item["data"], &item["char_count"]), (&Value::from("plan"), &Value::from("***"), &Value::from("***"), &Value::from(11)));
+        let listed = body(svc.notebook_list(args(serde_json::json!({}))).await.unwrap());
+        assert_eq!(listed["items"][0]["snippet"], "***");
+    }
+
+    #[tokio::test]
+    async fn allowlist_ignores_case() {
+        let svc = test_service(|s| { s.allowed_tables = Some(HashSet::from(["notes".to_string()])); }).await;
+        assert!(check_table_access(&svc.state, "NOTES").is_ok());
+        assert!(check_table_access(&svc.state, "Other").is_err());
+    }
+
+    #[test]
+    fn strips_line_comments() {
+        assert_eq!(strip_sql_comments("id = 1 -- LIMIT is appended after this"), "id = 1  ");
+        assert_eq!(strip_sql_comments("-- note\nid = 1"), " id = 1");
+        assert_eq!(strip_sql_comments("a - -b"), "a - -b");
+    }
+
+    #[test]
+    fn strips_block_comments() {
+        assert_eq!(strip_sql_comments("id = 1 /* x */ AND b = 2"), "id = 1   AND b = 2");
+        assert_eq!(strip_sql_comments("a /* x /* y */ z */ b"), "a   b");
+    }
+
+    #[test]
+    fn unterminated_block_comment_runs_to_end() {
+        assert_eq!(strip_sql_comments("id = 1 /* open"), "id = 1  ");
+    }
+
+    #[test]
+    fn keeps_comment_markers_in_quotes() {
+        assert_eq!(strip_sql_comments("title = '/* keep */'"), "title = '/* keep */'");
+        assert_eq!(strip_sql_comments("title = '-- keep' -- drop"), "title = '-- keep'  ");
+        assert_eq!(strip_sql_comments("[col--name] = 1"), "[col--name] = 1");
+        assert_eq!(strip_sql_comments("`a/*b` = 1"), "`a/*b` = 1");
+    }
+
+    #[test]
+    fn doubled_quotes_stay_inside_literals() {
+        assert_eq!(strip_sql_comments("title = 'it''s -- here' -- gone"), "title = 'it''s -- here'  ");
+        assert_eq!(strip_sql_comments("\"a\"\"--b\" = 1"), "\"a\"\"--b\" = 1");
+    }
+
+    #[test]
+    fn unterminated_literal_keeps_the_rest() {
+        assert_eq!(strip_sql_comments("title = 'abc -- x"), "title = 'abc -- x");
+    }
+}